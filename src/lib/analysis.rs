@@ -0,0 +1,665 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use crate::lib::base1::Base1Expr;
+use crate::lib::parser::{Node, LogicOp, CmpOp, UnOp};
+use crate::lib::scope::Value;
+
+/// Upper bound on distinct atoms of an analyzed condition set, 2^20 assignments
+const MAX_ATOMS: usize = 20;
+
+/// Upper bound for `gaps`, minimizing the uncovered assignments grows much faster than
+/// enumerating them
+const MAX_GAP_ATOMS: usize = 12;
+
+/// Smallest part of a condition the analysis assigns a truth value to
+#[derive(Debug, Clone, PartialEq)]
+enum Atom {
+    /// Boolean input
+    Var(String),
+    /// `name op constant`, solved for a concrete value
+    Bound(String, CmpOp, f64),
+    /// Any other comparison, its truth value is only assumed
+    Opaque(Node),
+}
+
+impl Atom {
+    /// Atom of the comparison and whether the comparison is the atom or its negation.
+    /// A constant goes to the right and only `>`, `>=` and `==` are atoms, so `D + E <= 3`
+    /// and `3 < D + E` are both the atom `D + E > 3`
+    fn compare(op: CmpOp, l: &Node, r: &Node) -> (Atom, bool) {
+        let (op, l, r) = match (constant(l), constant(r)) {
+            (Some(_), None) => (op.flip(), r, l),
+            _ => (op, l, r),
+        };
+        let (op, holds) = match op {
+            CmpOp::Lt | CmpOp::Le | CmpOp::Ne => (op.negate(), false),
+            CmpOp::Gt | CmpOp::Ge | CmpOp::Eq => (op, true),
+        };
+        let atom = match (l, constant(r)) {
+            (Node::Var(name), Some(c)) => Atom::Bound(name.clone(), op, c),
+            _ => Atom::Opaque(Node::Compare(op, Box::new(l.clone()), Box::new(r.clone()))),
+        };
+        (atom, holds)
+    }
+}
+
+/// Value of a number literal, possibly signed
+pub(crate) fn constant(node: &Node) -> Option<f64> {
+    match node {
+        Node::Const(v) => Some(*v),
+        Node::Unary(UnOp::Neg, n) => constant(n).map(|v| -v),
+        Node::Unary(UnOp::Pos, n) => constant(n),
+        _ => None,
+    }
+}
+
+/// Value satisfying every `x op c` bound. The solution set is a union of intervals
+/// with constants as endpoints, so the constants, their integer neighbours and the
+/// midpoints between them cover it. Integers are tried first, an `int` input only gets those
+fn solve(bounds: &[(CmpOp, f64)], int: bool) -> Option<f64> {
+    let mut consts: Vec<f64> = bounds.iter().map(|b| b.1).collect();
+    consts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    consts.dedup();
+
+    let mut candidates = vec![0.0];
+    for c in &consts {
+        candidates.extend(&[c.floor() - 1.0, c.floor(), c.ceil(), c.ceil() + 1.0]);
+    }
+    if !int {
+        candidates.extend(&consts);
+        for w in consts.windows(2) {
+            candidates.push((w[0] + w[1]) / 2.0);
+        }
+    }
+    candidates.into_iter().find(|v| bounds.iter().all(|(op, c)| op.apply(*v, *c)))
+}
+
+/// Comparison the witness could not solve and the truth value it relies on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Assumption {
+    pub condition: String,
+    pub holds: bool,
+}
+
+/// Concrete input for which the analyzed conditions hold
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Witness {
+    pub inputs: BTreeMap<String, Value>,
+    pub assumptions: Vec<Assumption>,
+}
+
+/// Distinct atoms of a set of conditions, an assignment is a bit mask over them.
+/// `ints` are the inputs only taking whole numbers
+pub(crate) struct Atoms<'a> {
+    atoms: Vec<Atom>,
+    ints: &'a [String],
+}
+
+impl<'a> Atoms<'a> {
+    pub fn collect(conditions: &[&Node], ints: &'a [String]) -> Result<Atoms<'a>> {
+        Atoms::collect_at_most(conditions, ints, MAX_ATOMS)
+    }
+
+    fn collect_at_most(conditions: &[&Node], ints: &'a [String], max: usize) -> Result<Atoms<'a>> {
+        let mut atoms = Atoms {
+            atoms: vec![],
+            ints,
+        };
+        for c in conditions {
+            atoms.visit(c);
+        }
+        if atoms.atoms.len() > max {
+            return Err(anyhow!("too many conditions to analyze, found {} atoms, at most {}", atoms.atoms.len(), max));
+        }
+        Ok(atoms)
+    }
+
+    fn visit(&mut self, node: &Node) {
+        let atom = match node {
+            Node::Var(name) => Atom::Var(name.clone()),
+            Node::Compare(op, l, r) => Atom::compare(*op, l, r).0,
+            Node::Not(n) => return self.visit(n),
+            Node::Logic(_, l, r) => {
+                self.visit(l);
+                return self.visit(r);
+            },
+            _ => return,
+        };
+        if !self.atoms.contains(&atom) {
+            self.atoms.push(atom);
+        }
+    }
+
+    fn index(&self, atom: &Atom) -> usize {
+        self.atoms.iter().position(|a| a == atom).expect("atom was collected")
+    }
+
+    /// Truth value of a collected condition under the assignment
+    pub fn eval(&self, node: &Node, mask: u64) -> bool {
+        let bit = |atom: Atom| mask & (1 << self.index(&atom)) != 0;
+        match node {
+            Node::Var(name) => bit(Atom::Var(name.clone())),
+            Node::Compare(op, l, r) => {
+                let (atom, holds) = Atom::compare(*op, l, r);
+                bit(atom) == holds
+            },
+            Node::Not(n) => !self.eval(n, mask),
+            Node::Logic(LogicOp::And, l, r) => self.eval(l, mask) && self.eval(r, mask),
+            Node::Logic(LogicOp::Or, l, r) => self.eval(l, mask) || self.eval(r, mask),
+            n => panic!("numeric node {} in condition", n),
+        }
+    }
+
+    /// Input realizing the assignment, `None` if the numeric bounds contradict each other
+    /// or a variable is used both as bool and number
+    pub fn witness(&self, mask: u64) -> Option<Witness> {
+        let mut inputs = BTreeMap::new();
+        let mut bounds: BTreeMap<&str, Vec<(CmpOp, f64)>> = BTreeMap::new();
+        let mut assumptions = vec![];
+        for (i, atom) in self.atoms.iter().enumerate() {
+            let holds = mask & (1 << i) != 0;
+            match atom {
+                Atom::Var(name) => {
+                    inputs.insert(name.clone(), Value::Bool(holds));
+                },
+                Atom::Bound(name, op, c) => {
+                    let op = if holds { *op } else { op.negate() };
+                    bounds.entry(name.as_str()).or_default().push((op, *c));
+                },
+                Atom::Opaque(n) => assumptions.push(Assumption {
+                    condition: n.to_string(),
+                    holds,
+                }),
+            }
+        }
+        for (name, bs) in bounds {
+            if inputs.contains_key(name) {
+                return None;
+            }
+            let v = solve(&bs, self.ints.iter().any(|i| i == name))?;
+            let v = if v.fract() == 0.0 { Value::Int(v as i64) } else { Value::Float(v) };
+            inputs.insert(name.to_string(), v);
+        }
+        Some(Witness {
+            inputs,
+            assumptions,
+        })
+    }
+
+    /// Witness of the first realizable assignment accepted by `pred`
+    pub fn find<P: Fn(u64) -> bool>(&self, pred: P) -> Option<Witness> {
+        (0..1u64 << self.atoms.len())
+            .filter(|m| pred(*m))
+            .find_map(|m| self.witness(m))
+    }
+
+    /// Bits of the atoms whose truth value is only assumed
+    fn opaque(&self) -> u64 {
+        self.atoms.iter().enumerate()
+            .filter(|(_, a)| matches!(a, Atom::Opaque(_)))
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+}
+
+/// Every mask with only bits of `mask` set, `mask` itself first
+fn submasks(mask: u64) -> impl Iterator<Item = u64> {
+    let mut next = Some(mask);
+    std::iter::from_fn(move || {
+        let m = next?;
+        next = if m == 0 { None } else { Some((m - 1) & mask) };
+        Some(m)
+    })
+}
+
+/// Input for which both conditions hold, if there is one. Comparisons the analysis can
+/// not solve must not matter, both conditions hold whatever their truth values
+pub fn overlap(a: &Node, b: &Node, ints: &[String]) -> Result<Option<Witness>> {
+    let atoms = Atoms::collect(&[a, b], ints)?;
+    let opaque = atoms.opaque();
+    let witness = atoms.find(|m| m & opaque == 0 && submasks(opaque).all(|o| atoms.eval(a, m | o) && atoms.eval(b, m | o)));
+    Ok(witness.map(|w| Witness { assumptions: vec![], ..w }))
+}
+
+/// Whether some realizable input makes the condition hold
+pub fn satisfiable(c: &Node, ints: &[String]) -> Result<bool> {
+    let atoms = Atoms::collect(&[c], ints)?;
+    Ok(atoms.find(|m| atoms.eval(c, m)).is_some())
+}
+
+/// Whether every input making `c` hold also makes one of `by` hold
+pub fn implied(c: &Node, by: &[&Node], ints: &[String]) -> Result<bool> {
+    let mut all = vec![c];
+    all.extend(by);
+    let atoms = Atoms::collect(&all, ints)?;
+    Ok(atoms.find(|m| atoms.eval(c, m) && !by.iter().any(|b| atoms.eval(b, m))).is_none())
+}
+
+/// Set of numeric values allowed for an input, bounds missing when unrestricted
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Range {
+    pub min: Option<f64>,
+    pub min_inclusive: bool,
+    pub max: Option<f64>,
+    pub max_inclusive: bool,
+    pub excluded: Vec<f64>,
+}
+
+impl Range {
+    fn any() -> Range {
+        Range {
+            min: None,
+            min_inclusive: false,
+            max: None,
+            max_inclusive: false,
+            excluded: vec![],
+        }
+    }
+
+    fn restrict(&mut self, op: CmpOp, c: f64) {
+        match op {
+            CmpOp::Gt | CmpOp::Ge => {
+                let inclusive = op == CmpOp::Ge;
+                let tighter = match self.min {
+                    Some(min) => c > min || (c == min && !inclusive),
+                    None => true,
+                };
+                if tighter {
+                    self.min = Some(c);
+                    self.min_inclusive = inclusive;
+                }
+            },
+            CmpOp::Lt | CmpOp::Le => {
+                let inclusive = op == CmpOp::Le;
+                let tighter = match self.max {
+                    Some(max) => c < max || (c == max && !inclusive),
+                    None => true,
+                };
+                if tighter {
+                    self.max = Some(c);
+                    self.max_inclusive = inclusive;
+                }
+            },
+            CmpOp::Eq => {
+                self.restrict(CmpOp::Ge, c);
+                self.restrict(CmpOp::Le, c);
+            },
+            CmpOp::Ne => self.excluded.push(c),
+        }
+    }
+
+    fn contains(&self, v: f64) -> bool {
+        let above = match self.min {
+            Some(min) => v > min || (v == min && self.min_inclusive),
+            None => true,
+        };
+        let below = match self.max {
+            Some(max) => v < max || (v == max && self.max_inclusive),
+            None => true,
+        };
+        above && below && !self.excluded.contains(&v)
+    }
+}
+
+/// Product term over the atoms, `care` marks the atoms it mentions and `value` their polarity
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+struct Term {
+    value: u64,
+    care: u64,
+}
+
+impl Term {
+    fn covers(self, mask: u64) -> bool {
+        mask & self.care == self.value
+    }
+}
+
+/// Quine-McCluskey: prime implicants of `on` with the help of `dc`, then the essential
+/// ones and greedily the ones covering most of what is left
+fn minimize(on: &[u64], dc: &[u64], n: usize) -> Vec<Term> {
+    let full = (1u64 << n) - 1;
+    let mut current: HashSet<Term> = on.iter().chain(dc).map(|m| Term {
+        value: *m,
+        care: full,
+    }).collect();
+    let mut primes = vec![];
+    while !current.is_empty() {
+        let mut next = HashSet::new();
+        let mut merged = HashSet::new();
+        for t in &current {
+            for bit in (0..n).map(|b| 1u64 << b) {
+                if t.care & bit == 0 || t.value & bit != 0 {
+                    continue;
+                }
+                let other = Term {
+                    value: t.value | bit,
+                    care: t.care,
+                };
+                if current.contains(&other) {
+                    next.insert(Term {
+                        value: t.value,
+                        care: t.care & !bit,
+                    });
+                    merged.insert(*t);
+                    merged.insert(other);
+                }
+            }
+        }
+        primes.extend(current.into_iter().filter(|t| !merged.contains(t)));
+        current = next;
+    }
+    primes.sort_by_key(|t| (t.care.count_ones(), t.care, t.value));
+
+    let mut left: Vec<u64> = on.to_vec();
+    let mut chosen = vec![];
+    for m in on {
+        let covering: Vec<&Term> = primes.iter().filter(|t| t.covers(*m)).collect();
+        if covering.len() == 1 && !chosen.contains(covering[0]) {
+            chosen.push(*covering[0]);
+        }
+    }
+    left.retain(|m| !chosen.iter().any(|t| t.covers(*m)));
+    while !left.is_empty() {
+        let best = *primes.iter()
+            .max_by_key(|t| (left.iter().filter(|m| t.covers(**m)).count(), std::cmp::Reverse(t.care.count_ones())))
+            .expect("every minterm is covered by a prime");
+        chosen.push(best);
+        left.retain(|m| !best.covers(*m));
+    }
+    chosen.sort_by_key(|t| (t.care.count_ones(), t.care, t.value));
+    chosen
+}
+
+/// Region of inputs no condition holds for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Gap {
+    /// Conjunction describing the region, each literal is a valid condition, empty for every input
+    pub literals: Vec<String>,
+    /// Comparisons of the region merged per numeric input
+    pub ranges: BTreeMap<String, Range>,
+    pub example: Witness,
+}
+
+/// Uncovered input regions of the conditions as minimized terms
+pub fn gaps(conditions: &[&Node], ints: &[String]) -> Result<Vec<Gap>> {
+    let atoms = Atoms::collect_at_most(conditions, ints, MAX_GAP_ATOMS)?;
+    let mut on = vec![];
+    let mut dc = vec![];
+    for m in 0..1u64 << atoms.atoms.len() {
+        if atoms.witness(m).is_none() {
+            dc.push(m);
+        } else if !conditions.iter().any(|c| atoms.eval(c, m)) {
+            on.push(m);
+        }
+    }
+
+    let gaps = minimize(&on, &dc, atoms.atoms.len()).into_iter().map(|t| {
+        let mut literals = vec![];
+        let mut ranges: BTreeMap<String, Range> = BTreeMap::new();
+        for (i, atom) in atoms.atoms.iter().enumerate() {
+            let bit = 1u64 << i;
+            if t.care & bit == 0 {
+                continue;
+            }
+            let holds = t.value & bit != 0;
+            let literal = match atom {
+                Atom::Var(name) => Node::Var(name.clone()),
+                Atom::Bound(name, op, c) => {
+                    let op = if holds { *op } else { op.negate() };
+                    ranges.entry(name.clone()).or_insert_with(Range::any).restrict(op, *c);
+                    literals.push(Node::Compare(op, Box::new(Node::Var(name.clone())), Box::new(Node::Const(*c))).to_string());
+                    continue;
+                },
+                Atom::Opaque(n) => n.clone(),
+            };
+            literals.push(if holds { literal } else { Node::Not(Box::new(literal)) }.to_string());
+        }
+        for r in ranges.values_mut() {
+            let excluded: Vec<f64> = r.excluded.drain(..).collect();
+            for v in excluded {
+                if r.contains(v) {
+                    r.excluded.push(v);
+                }
+            }
+        }
+        let m = on.iter().find(|m| t.covers(**m)).expect("term covers an uncovered input");
+        Gap {
+            literals,
+            ranges,
+            example: atoms.witness(*m).expect("uncovered inputs are realizable"),
+        }
+    }).collect();
+    Ok(gaps)
+}
+
+/// Result of the completeness check of an `App`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Coverage {
+    pub gaps: Vec<Gap>,
+    /// Categories produced by a condition without a `H = X => K = ...` formula
+    pub missing_formulas: Vec<String>,
+}
+
+impl Coverage {
+    pub fn is_complete(&self) -> bool {
+        self.gaps.is_empty() && self.missing_formulas.is_empty()
+    }
+}
+
+/// Base1 rule referenced by a report, `index` is its position among the `App` rules
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleRef {
+    pub index: usize,
+    pub condition: String,
+    pub category: String,
+}
+
+impl RuleRef {
+    pub fn new(index: usize, e: &Base1Expr) -> RuleRef {
+        RuleRef {
+            index,
+            condition: e.condition().to_string(),
+            category: e.output().to_string(),
+        }
+    }
+}
+
+/// Two rules with different categories which can fire for the same input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Overlap {
+    pub first: RuleRef,
+    pub second: RuleRef,
+    pub witness: Witness,
+}
+
+/// Problem found by `App::lint`, the `Display` form is meant for terminals and logs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Diagnostic {
+    /// No input makes the condition hold
+    Unsatisfiable { rule: RuleRef },
+    /// Rules tried before cover every input of the rule, `by` are those overlapping it
+    Shadowed { rule: RuleRef, by: Vec<RuleRef> },
+    /// Formula of a category no condition produces
+    OrphanFormula { index: usize, category: String },
+    /// Condition too large to analyze, the other rules are still checked
+    Skipped { rule: RuleRef, reason: String },
+}
+
+impl Diagnostic {
+    /// Position of the offending rule among the `App` rules
+    pub fn index(&self) -> usize {
+        match self {
+            Diagnostic::Unsatisfiable { rule } | Diagnostic::Shadowed { rule, .. } | Diagnostic::Skipped { rule, .. } => rule.index,
+            Diagnostic::OrphanFormula { index, .. } => *index,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::Unsatisfiable { rule } => {
+                write!(f, "rule {} `{} => H = {}` can never match, the condition is unsatisfiable",
+                       rule.index, rule.condition, rule.category)
+            },
+            Diagnostic::Shadowed { rule, by } => {
+                let by: Vec<String> = by.iter().map(|r| r.index.to_string()).collect();
+                write!(f, "rule {} `{} => H = {}` can never fire, it is shadowed by {} {}",
+                       rule.index, rule.condition, rule.category,
+                       if by.len() == 1 { "rule" } else { "rules" }, by.join(", "))
+            },
+            Diagnostic::OrphanFormula { index, category } => {
+                write!(f, "formula {} for category {} is unreachable, no rule produces {}", index, category, category)
+            },
+            Diagnostic::Skipped { rule, reason } => {
+                write!(f, "rule {} `{} => H = {}` was not checked, {}", rule.index, rule.condition, rule.category, reason)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::parser::{Node, Parser};
+    use crate::lib::scope::Value;
+    use crate::lib::tokenize::tokenize;
+    use super::{gaps, implied, overlap, satisfiable, solve, Atoms, Witness, Range};
+
+    fn node(s: &str) -> Node {
+        Parser::parse(&tokenize(s).unwrap()).unwrap()
+    }
+
+    fn witness(a: &str, b: &str) -> Option<Witness> {
+        overlap(&node(a), &node(b), &[]).unwrap()
+    }
+
+    fn inputs(w: &Witness) -> Vec<(&str, Value)> {
+        w.inputs.iter().map(|(k, v)| (k.as_str(), *v)).collect()
+    }
+
+    #[test]
+    fn test_solve() {
+        use crate::lib::parser::CmpOp::*;
+        assert_eq!(Some(0.0), solve(&[], false));
+        assert_eq!(Some(101.0), solve(&[(Gt, 100.0)], false));
+        assert_eq!(Some(3.0), solve(&[(Ge, 2.5), (Ne, 3.0 - 0.5), (Lt, 4.0)], false));
+        assert_eq!(Some(2.75), solve(&[(Gt, 2.5), (Lt, 3.0)], false));
+        assert_eq!(Some(5.0), solve(&[(Eq, 5.0), (Le, 5.0)], false));
+        assert_eq!(None, solve(&[(Gt, 10.0), (Lt, 5.0)], false));
+        assert_eq!(None, solve(&[(Ge, 1.0), (Le, 1.0), (Ne, 1.0)], false));
+        assert_eq!(None, solve(&[(Gt, 2.0), (Lt, 3.0)], true));
+        assert_eq!(Some(5.0), solve(&[(Gt, 2.0), (Ne, 3.0), (Ne, 4.0)], true));
+        assert_eq!(None, solve(&[(Eq, 2.5)], true));
+    }
+
+    #[test]
+    fn test_overlap() {
+        let w = witness("A && B", "A && !C").unwrap();
+        assert_eq!(vec![("A", Value::Bool(true)), ("B", Value::Bool(true)), ("C", Value::Bool(false))], inputs(&w));
+        assert!(w.assumptions.is_empty());
+
+        assert_eq!(None, witness("A && B && !C", "A && B && C"));
+        assert_eq!(None, witness("A && !A", "B"));
+
+        let w = witness("order_total > 100", "is_vip || 50 <= order_total").unwrap();
+        assert_eq!(vec![("is_vip", Value::Bool(false)), ("order_total", Value::Int(101))], inputs(&w));
+        assert_eq!(None, witness("D > 10", "D < 5 || D == 10"));
+        assert_eq!(None, witness("A", "A > 1"));
+    }
+
+    #[test]
+    fn test_assumptions() {
+        // an overlap relying on a comparison which is not solved is not reported
+        assert_eq!(None, witness("D + E > 3", "D < 0"));
+        assert_eq!(None, witness("D + E > 3", "D + E <= 3"));
+        assert_eq!(None, witness("3 < D + E && A", "!(D + E > 3)"));
+        let w = witness("D + E > 3 || A", "A && D > 1").unwrap();
+        assert_eq!(vec![("A", Value::Bool(true)), ("D", Value::Int(2))], inputs(&w));
+        assert!(w.assumptions.is_empty());
+
+        // the examples of gaps list them
+        let gs = gaps(&[&node("D + E > 3"), &node("D < 0")], &[]).unwrap();
+        assert_eq!(vec!["!(D + E > 3)", "D >= 0"], gs[0].literals);
+        assert_eq!("D + E > 3", gs[0].example.assumptions[0].condition);
+        assert!(!gs[0].example.assumptions[0].holds);
+    }
+
+    #[test]
+    fn test_int_inputs() {
+        let ints = ["E".to_string()];
+        assert!(satisfiable(&node("E > 2 && E < 3"), &[]).unwrap());
+        assert!(!satisfiable(&node("E > 2 && E < 3"), &ints).unwrap());
+        assert!(!satisfiable(&node("E == 2.5"), &ints).unwrap());
+        let w = overlap(&node("E > 2"), &node("E <= 3.5"), &ints).unwrap().unwrap();
+        assert_eq!(vec![("E", Value::Int(3))], inputs(&w));
+        assert_eq!(None, overlap(&node("E > 2"), &node("E < 3"), &ints).unwrap());
+        assert!(implied(&node("E > 2 && E < 4"), &[&node("E == 3")], &ints).unwrap());
+    }
+
+    #[test]
+    fn test_too_many_atoms() {
+        let vars: Vec<String> = (0..21).map(|i| format!("X{}", i)).collect();
+        let n = node(&vars.join(" && "));
+        assert_eq!("too many conditions to analyze, found 21 atoms, at most 20",
+                   Atoms::collect(&[&n], &[]).err().unwrap().to_string());
+        let n = node(&vars[..13].join(" || "));
+        assert_eq!("too many conditions to analyze, found 13 atoms, at most 12",
+                   gaps(&[&n], &[]).err().unwrap().to_string());
+    }
+
+    fn literals(conditions: &[&str]) -> Vec<Vec<String>> {
+        let nodes: Vec<Node> = conditions.iter().map(|c| node(c)).collect();
+        let refs: Vec<&Node> = nodes.iter().collect();
+        gaps(&refs, &[]).unwrap().into_iter().map(|g| g.literals).collect()
+    }
+
+    #[test]
+    fn test_gaps() {
+        assert_eq!(vec![vec!["!B"], vec!["!A", "!C"]],
+                   literals(&["A && B && !C", "A && B && C", "!A && B && C"]));
+        assert_eq!(Vec::<Vec<String>>::new(), literals(&["A || B", "!A && !B"]));
+        assert_eq!(vec![Vec::<String>::new()], literals(&[]));
+        assert_eq!(vec![vec!["A", "!(D + E > 3)"]], literals(&["!A", "D + E > 3"]));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let n = [node("D > 100"), node("D <= 10 || D == 50")];
+        let gs = gaps(&[&n[0], &n[1]], &[]).unwrap();
+        assert_eq!(1, gs.len());
+        assert_eq!(vec!["D <= 100", "D > 10", "D != 50"], gs[0].literals);
+        assert_eq!(Range {
+            min: Some(10.0),
+            min_inclusive: false,
+            max: Some(100.0),
+            max_inclusive: true,
+            excluded: vec![50.0],
+        }, gs[0].ranges["D"]);
+        assert_eq!(Some(&Value::Int(11)), gs[0].example.inputs.get("D"));
+
+        // the bound implied by a stricter one is dropped
+        let n = [node("E > 5"), node("E >= 3 && A")];
+        let gs = gaps(&[&n[0], &n[1]], &[]).unwrap();
+        let ls: Vec<&Vec<String>> = gs.iter().map(|g| &g.literals).collect();
+        assert_eq!(vec![&vec!["E < 3".to_string()], &vec!["E <= 5".to_string(), "!A".to_string()]], ls);
+    }
+
+    #[test]
+    fn test_implied() {
+        assert!(!satisfiable(&node("A && !A"), &[]).unwrap());
+        assert!(!satisfiable(&node("D > 10 && D < 5"), &[]).unwrap());
+        assert!(!satisfiable(&node("D + E > 3 && D + E <= 3"), &[]).unwrap());
+        assert!(!satisfiable(&node("3 < D + E && !(D + E > 3)"), &[]).unwrap());
+        assert!(!satisfiable(&node("2 == D * E && D * E != 2"), &[]).unwrap());
+        assert!(!satisfiable(&node("is_vip && is_vip > 0"), &[]).unwrap());
+        assert!(satisfiable(&node("D > 10 && D < 11"), &[]).unwrap());
+
+        let (c, a, b) = (node("B && C"), node("A && B && C"), node("!A && C"));
+        assert!(implied(&c, &[&a, &b], &[]).unwrap());
+        assert!(!implied(&c, &[&a], &[]).unwrap());
+        assert!(implied(&node("D > 100 && A"), &[&node("D >= 50")], &[]).unwrap());
+        assert!(!implied(&node("D > 10"), &[&node("D >= 50")], &[]).unwrap());
+    }
+}
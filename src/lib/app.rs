@@ -1,74 +1,678 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use crate::lib::expr::Expr;
+use crate::lib::base1::Base1Expr;
+use crate::lib::base2::Base2Expr;
+use crate::lib::trace::{Explanation, FormulaTrace, RuleTrace, Selection};
+use crate::lib::parser::Node;
 use crate::lib::scope::Scope;
 use crate::lib::output;
+use crate::lib::functions::{Arity, Functions};
+use crate::lib::rule::{Rule, RuleSet, RuleSource};
+use crate::lib::analysis::{self, Coverage, Diagnostic, Overlap, RuleRef};
+use crate::lib::error::{render_all, RuleError};
+use crate::lib::sprout;
+use crate::lib::document::{Document, Format};
+use crate::lib::table::{self, DecisionTable};
+use crate::lib::dmn;
+use crate::lib::jsonlogic::LogicRule;
+use std::fs;
+use std::path::Path;
+
+/// How `App::run` picks among several Base1 rules whose conditions hold
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// The earliest added rule wins
+    FirstMatch,
+    /// The rule with the highest priority wins, ties go to the earliest added
+    HighestPriority,
+    /// Matching rules producing different categories are an error
+    ErrorOnAmbiguity,
+    /// Every matched category is returned
+    CollectAll,
+}
+
+impl Strategy {
+    /// Strategy of a decision table hit policy, `UNIQUE`, `FIRST`, `PRIORITY` or `COLLECT`
+    /// in any case or their first letter
+    pub fn from_hit_policy(name: &str) -> Option<Strategy> {
+        match name.to_ascii_uppercase().as_str() {
+            "UNIQUE" | "U" => Some(Strategy::ErrorOnAmbiguity),
+            "FIRST" | "F" => Some(Strategy::FirstMatch),
+            "PRIORITY" | "P" => Some(Strategy::HighestPriority),
+            "COLLECT" | "C" => Some(Strategy::CollectAll),
+            _ => None,
+        }
+    }
+
+    pub fn hit_policy(self) -> &'static str {
+        match self {
+            Strategy::ErrorOnAmbiguity => "UNIQUE",
+            Strategy::FirstMatch => "FIRST",
+            Strategy::HighestPriority => "PRIORITY",
+            Strategy::CollectAll => "COLLECT",
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct App {
-    exprs: Vec<Expr>,
+    categories: Vec<output::H>,
+    functions: Functions,
+    strategy: Strategy,
+    exprs: Vec<Rule>,
+    next_id: u64,
+    ints: Vec<String>,
 }
 
 impl App {
     pub fn new() -> App {
         App {
+            categories: vec![],
+            functions: Functions::default(),
+            strategy: Strategy::FirstMatch,
             exprs: vec![],
+            next_id: 1,
+            ints: vec![],
         }
     }
 
     pub fn default() -> App {
-        let app = App::new();
+        let app = App::new()
+            .category("M")
+            .category("P")
+            .category("T")
+            .int_input("E")
+            .int_input("F");
         let app = app.add("A && B && !C => H = M").unwrap();
         let app = app.add("A && B && C => H = P").unwrap();
         let app = app.add("!A && B && C => H = T").unwrap();
         let app = app.add("H = M => K = D + (D * E / 10)").unwrap();
         let app = app.add("H = P => K = D + (D * (E - F) / 25.5)").unwrap();
-        let app = app.add("H = T => K = D - (D * F / 30)").unwrap();
-        return app
+        app.add("H = T => K = D - (D * F / 30)").unwrap()
+    }
+
+    /// Declares a category rules are allowed to use in `H = <name>`
+    pub fn category(&self, name: &str) -> App {
+        let mut app = self.clone();
+        let h = output::H::new(name);
+        if !app.categories.contains(&h) {
+            app.categories.push(h);
+        }
+        app
+    }
+
+    pub fn categories(&self) -> &[output::H] {
+        &self.categories
+    }
+
+    /// Declares a numeric input taking only whole numbers, the analysis picks no
+    /// fractions for it. Declarations are kept when the rules are replaced
+    pub fn int_input(&self, name: &str) -> App {
+        let mut app = self.clone();
+        if !app.ints.iter().any(|i| i == name) {
+            app.ints.push(name.to_string());
+        }
+        app
+    }
+
+    pub fn int_inputs(&self) -> &[String] {
+        &self.ints
+    }
+
+    pub fn with_strategy(&self, strategy: Strategy) -> App {
+        let mut app = self.clone();
+        app.strategy = strategy;
+        app
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// Registers a function callable from conditions and formulas added afterwards,
+    /// rules added before keep the function they were built with
+    pub fn register_function<F>(&self, name: &str, arity: Arity, f: F) -> Result<App>
+        where F: Fn(&[f64]) -> Result<f64> + Send + Sync + 'static {
+        let mut app = self.clone();
+        app.functions.register(name, arity, f)?;
+        Ok(app)
     }
 
     pub fn add(&self, expr: &str) -> Result<App> {
-        let ok = Expr::from_str(expr)?;
-        Ok(self.add_expr(ok))
+        self.add_with_priority(expr, 0)
     }
 
-    pub fn add_expr(&self, e: Expr) -> App {
-        let exprs = self.exprs.clone();
-        let mut exprs: Vec<Expr> = exprs.into_iter().filter_map(|x| {
-            if x == e {
-                None
-            } else {
-                Some(x)
+    /// Adds every rule of the batch, a bad rule is skipped so the errors of all of them
+    /// are returned together, each with the index of its rule
+    pub fn add_all<S: AsRef<str>>(&self, exprs: &[S]) -> std::result::Result<App, Vec<RuleError>> {
+        let mut app = self.clone();
+        let mut errors = vec![];
+        for (index, expr) in exprs.iter().enumerate() {
+            match app.add(expr.as_ref()) {
+                Ok(a) => app = a,
+                Err(e) => errors.extend(RuleError::from_error(index, &e)),
             }
-        }).collect();
-        exprs.push(e);
-        App {
-            exprs
         }
+        if errors.is_empty() {
+            return Ok(app);
+        }
+        Err(errors)
+    }
+
+    pub fn add_with_priority(&self, expr: &str, priority: i64) -> Result<App> {
+        let ok = Expr::from_str_with(expr, &self.functions)?;
+        self.add_rule(Rule::new(ok).with_source(expr).with_priority(priority))
+    }
+
+    pub fn add_expr(&self, e: Expr) -> Result<App> {
+        self.add_rule(Rule::new(e))
+    }
+
+    /// Appends the rule with the next id. A rule compiling to the same program is
+    /// replaced, the new one keeps its id
+    pub fn add_rule(&self, r: Rule) -> Result<App> {
+        self.check_category(&r)?;
+        let mut app = self.clone();
+        let id = match app.exprs.iter().position(|x| x.expr() == r.expr()) {
+            Some(pos) => app.exprs.remove(pos).id(),
+            None => {
+                app.next_id += 1;
+                app.next_id - 1
+            },
+        };
+        app.exprs.push(r.with_id(id));
+        Ok(app)
+    }
+
+    /// Replaces the rule with the id in place, or appends the rule under this id
+    pub fn put_rule(&self, id: u64, r: Rule) -> Result<App> {
+        self.check_category(&r)?;
+        if let Some(other) = self.exprs.iter().find(|x| x.id() != id && x.expr() == r.expr()) {
+            return Err(anyhow!("rule {} has the same expression", other.id()));
+        }
+        let mut app = self.clone();
+        let r = r.with_id(id);
+        match app.exprs.iter().position(|x| x.id() == id) {
+            Some(pos) => app.exprs[pos] = r,
+            None => app.exprs.push(r),
+        }
+        app.next_id = app.next_id.max(id + 1);
+        Ok(app)
+    }
+
+    fn check_category(&self, r: &Rule) -> Result<()> {
+        let e = r.expr();
+        if !self.categories.contains(e.category()) {
+            return Err(anyhow!("category {} is not declared", e.category()));
+        }
+        Ok(())
+    }
+
+    /// Builds the rule from its serialized form, the id is ignored
+    pub fn parse_rule(&self, r: &RuleSource) -> Result<Rule> {
+        let e = Expr::from_str_with(&r.source, &self.functions)?;
+        Ok(Rule::new(e)
+            .with_source(&r.source)
+            .with_priority(r.priority)
+            .with_name(r.name.clone())
+            .with_owner(r.owner.clone())
+            .with_description(r.description.clone()))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.exprs
+    }
+
+    pub fn rule(&self, id: u64) -> Option<&Rule> {
+        self.exprs.iter().find(|r| r.id() == id)
+    }
+
+    /// Removes the rule the expression compiles to, fails when there is none
+    pub fn remove(&self, expr: &str) -> Result<App> {
+        let e = Expr::from_str_with(expr, &self.functions)?;
+        let pos = self.exprs.iter().position(|r| r.expr() == &e)
+            .ok_or_else(|| anyhow!("no rule matches `{}`", expr.trim()))?;
+        let mut app = self.clone();
+        app.exprs.remove(pos);
+        Ok(app)
+    }
+
+    pub fn remove_rule(&self, id: u64) -> Result<App> {
+        let pos = self.exprs.iter().position(|r| r.id() == id)
+            .ok_or_else(|| anyhow!("rule {} not found", id))?;
+        let mut app = self.clone();
+        app.exprs.remove(pos);
+        Ok(app)
+    }
+
+    /// Categories, strategy and rules with their ids and metadata, the serializable form of the `App`
+    pub fn rule_set(&self) -> RuleSet {
+        RuleSet {
+            categories: self.categories.iter().map(|h| h.name().to_string()).collect(),
+            strategy: self.strategy,
+            rules: self.exprs.iter().map(Rule::to_source).collect(),
+            next_id: self.next_id,
+        }
+    }
+
+    /// Rebuilds the rules of the set with the functions of this `App`, replacing its
+    /// categories, strategy and rules. Rules keep their ids, those without get new ones
+    /// never given by this `App` before. Errors carry the index of the rule in the set
+    pub fn with_rule_set(&self, set: &RuleSet) -> std::result::Result<App, Vec<RuleError>> {
+        let mut app = App {
+            categories: vec![],
+            functions: self.functions.clone(),
+            strategy: set.strategy,
+            exprs: vec![],
+            next_id: set.next_id.max(self.next_id),
+            ints: self.ints.clone(),
+        };
+        for c in &set.categories {
+            app = app.category(c);
+        }
+        let mut errors = vec![];
+        let mut formulas = vec![];
+        for (index, r) in set.rules.iter().enumerate() {
+            let added = app.parse_rule(r).and_then(|rule| {
+                if let Expr::Base2(e) = rule.expr() {
+                    formulas.push((index, e.output().clone()));
+                }
+                match r.id {
+                    Some(id) => app.put_rule(id, rule),
+                    None => app.add_rule(rule),
+                }
+            });
+            match added {
+                Ok(a) => app = a,
+                Err(e) => errors.extend(RuleError::from_error(index, &e)),
+            }
+        }
+        if errors.is_empty() {
+            match app.validate() {
+                Ok(()) => return Ok(app),
+                // reported at the first formula of the category
+                Err(e) => {
+                    let h = app.unproduced();
+                    let index = formulas.iter().find(|(_, f)| Some(f) == h).map_or(0, |(i, _)| *i);
+                    errors.extend(RuleError::from_error(index, &e));
+                },
+            }
+        }
+        Err(errors)
+    }
+
+    /// Rules of a `.sprout` file, see `sprout::parse`
+    pub fn from_source(text: &str) -> Result<App> {
+        App::new().load_source(text, "")
+    }
+
+    /// Rules of a `.sprout` file, errors point at `path:line:column`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<App> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| anyhow!("can not read {}: {}", path.display(), e))?;
+        App::new().load_source(&text, &format!("{}:", path.display()))
+    }
+
+    /// Replaces categories and rules with those of the `.sprout` file, keeping the functions
+    /// and the strategy. `origin` is put before line and column in errors
+    pub fn load_source(&self, text: &str, origin: &str) -> Result<App> {
+        let file = sprout::parse(text).map_err(|e| anyhow!(e.render_at(text, origin)))?;
+        let set = RuleSet {
+            strategy: self.strategy,
+            ..file.set.clone()
+        };
+        self.with_rule_set(&set).map_err(|errors| anyhow!(file.render(text, &errors, origin)))
+    }
+
+    /// Categories, strategy and rules as a json, yaml or toml document, see `document::Document`.
+    /// With `trees` conditions and formulas are written as syntax trees instead of source text
+    pub fn export(&self, format: Format, trees: bool) -> Result<String> {
+        Document::from_app(self, trees).to_text(format)
+    }
+
+    /// Replaces categories, strategy and rules with those of the document, keeping the functions
+    pub fn import(&self, text: &str, format: Format) -> Result<App> {
+        Document::from_text(text, format)?.load(self)
+    }
+
+    /// Base1 rules as a csv decision table, see `table::parse`
+    pub fn export_table(&self) -> Result<String> {
+        DecisionTable::from_app(self)?.to_csv()
+    }
+
+    /// Replaces the Base1 rules with the rows of a csv decision table, see `DecisionTable::load`
+    pub fn import_table(&self, text: &str) -> Result<App> {
+        table::parse(text)?.load(self)
+    }
+
+    /// DMN 1.3 XML with the Base1 rules as decision table and the formulas as literal expressions
+    pub fn export_dmn(&self) -> Result<String> {
+        dmn::to_xml(self)
+    }
+
+    /// Replaces categories, strategy and rules with those of a DMN document, see `dmn::parse`
+    pub fn import_dmn(&self, xml: &str) -> Result<App> {
+        dmn::parse(xml)?.load(self)
+    }
+
+    /// Every rule with its condition or formula as JSON Logic
+    pub fn logic_rules(&self) -> Vec<LogicRule> {
+        self.exprs.iter().map(LogicRule::from_rule).collect()
+    }
+
+    /// Adds the JSON Logic rules, one with an id replaces that rule like `put_rule`.
+    /// Nothing is added unless every rule builds, errors point at `rules[i]`
+    pub fn add_logic(&self, rules: &[LogicRule]) -> Result<App> {
+        let mut app = self.clone();
+        let mut sources = vec![];
+        let mut errors = vec![];
+        for (index, logic) in rules.iter().enumerate() {
+            let r = match logic.to_source() {
+                Ok(r) => r,
+                Err(e) => {
+                    sources.push(String::new());
+                    errors.push(RuleError { rule: index, message: e.to_string(), syntax: None });
+                    continue;
+                },
+            };
+            sources.push(r.source.clone());
+            let added = app.parse_rule(&r).and_then(|rule| match r.id {
+                Some(id) => app.put_rule(id, rule),
+                None => app.add_rule(rule),
+            });
+            match added {
+                Ok(a) => app = a,
+                Err(e) => errors.extend(RuleError::from_error(index, &e)),
+            }
+        }
+        if errors.is_empty() {
+            return Ok(app);
+        }
+        Err(anyhow!(render_all(&errors, &sources, "rules")))
+    }
+
+    /// Checks that every category used by a Base2 rule is produced by some Base1 rule
+    pub fn validate(&self) -> Result<()> {
+        match self.unproduced() {
+            Some(h) => Err(anyhow!("category {} is not produced by any rule", h)),
+            None => Ok(()),
+        }
+    }
+
+    /// Category of the first Base2 rule no Base1 rule produces
+    fn unproduced(&self) -> Option<&output::H> {
+        self.exprs.iter().find_map(|r| match r.expr() {
+            Expr::Base2(e) if !self.base1().any(|(_, b)| b.output() == e.output()) => Some(e.output()),
+            _ => None,
+        })
+    }
+
+    /// Pairs of Base1 rules with different categories whose conditions can hold for the same
+    /// input, each with a witness. Found by enumerating the atoms of both conditions.
+    pub fn overlaps(&self) -> Result<Vec<Overlap>> {
+        let rules: Vec<(usize, &Base1Expr)> = self.base1().collect();
+
+        let mut overlaps = vec![];
+        for (n, (i, a)) in rules.iter().enumerate() {
+            for (j, b) in &rules[n + 1..] {
+                if a.output() == b.output() {
+                    continue;
+                }
+                if let Some(witness) = analysis::overlap(a.condition(), b.condition(), &self.ints)? {
+                    overlaps.push(Overlap {
+                        first: RuleRef::new(*i, a),
+                        second: RuleRef::new(*j, b),
+                        witness,
+                    });
+                }
+            }
+        }
+        Ok(overlaps)
+    }
+
+    /// Input regions no Base1 rule matches and categories without a formula, `run`
+    /// fails with "expr not found" or "expr for X not found" on those
+    pub fn coverage(&self) -> Result<Coverage> {
+        let conditions: Vec<&Node> = self.base1().map(|(_, e)| e.condition()).collect();
+        let mut missing_formulas: Vec<String> = vec![];
+        for (_, e) in self.base1() {
+            let name = e.output().to_string();
+            let has_formula = self.exprs.iter().any(|x| match x.expr() {
+                Expr::Base2(f) => f.output() == e.output(),
+                _ => false,
+            });
+            if !has_formula && !missing_formulas.contains(&name) {
+                missing_formulas.push(name);
+            }
+        }
+        Ok(Coverage {
+            gaps: analysis::gaps(&conditions, &self.ints)?,
+            missing_formulas,
+        })
+    }
+
+    /// Unsatisfiable conditions, Base1 rules the strategy never lets fire and Base2
+    /// formulas of categories nothing produces, ordered by rule position
+    pub fn lint(&self) -> Result<Vec<Diagnostic>> {
+        let mut diagnostics = vec![];
+        let mut live: Vec<(usize, &Base1Expr)> = vec![];
+        for (i, e) in self.base1() {
+            match analysis::satisfiable(e.condition(), &self.ints) {
+                Ok(true) => live.push((i, e)),
+                Ok(false) => diagnostics.push(Diagnostic::Unsatisfiable {
+                    rule: RuleRef::new(i, e),
+                }),
+                Err(err) => diagnostics.push(Diagnostic::Skipped {
+                    rule: RuleRef::new(i, e),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+
+        for (i, e) in &live {
+            let before: Vec<&(usize, &Base1Expr)> = live.iter().filter(|(j, _)| self.tried_before(*j, *i)).collect();
+            let conditions: Vec<&Node> = before.iter().map(|(_, b)| b.condition()).collect();
+            if before.is_empty() {
+                continue;
+            }
+            match analysis::implied(e.condition(), &conditions, &self.ints) {
+                Ok(true) => {},
+                Ok(false) => continue,
+                Err(err) => {
+                    diagnostics.push(Diagnostic::Skipped {
+                        rule: RuleRef::new(*i, e),
+                        reason: err.to_string(),
+                    });
+                    continue;
+                },
+            }
+            let mut by = vec![];
+            for (j, b) in before {
+                if analysis::overlap(b.condition(), e.condition(), &self.ints)?.is_some() {
+                    by.push(RuleRef::new(*j, b));
+                }
+            }
+            diagnostics.push(Diagnostic::Shadowed {
+                rule: RuleRef::new(*i, e),
+                by,
+            });
+        }
+
+        for (i, r) in self.exprs.iter().enumerate() {
+            if let Expr::Base2(e) = r.expr() {
+                if !self.base1().any(|(_, b)| b.output() == e.output()) {
+                    diagnostics.push(Diagnostic::OrphanFormula {
+                        index: i,
+                        category: e.output().to_string(),
+                    });
+                }
+            }
+        }
+        diagnostics.sort_by_key(|d| d.index());
+        Ok(diagnostics)
+    }
+
+    /// Whether the strategy picks rule `j` over rule `i` when both match, with
+    /// `ErrorOnAmbiguity` and `CollectAll` every matching rule counts
+    fn tried_before(&self, j: usize, i: usize) -> bool {
+        let (pj, pi) = (self.exprs[j].priority(), self.exprs[i].priority());
+        match self.strategy {
+            Strategy::FirstMatch => j < i,
+            Strategy::HighestPriority => pj > pi || (pj == pi && j < i),
+            Strategy::ErrorOnAmbiguity | Strategy::CollectAll => false,
+        }
+    }
+
+    /// Base1 rules with their position among all rules
+    fn base1(&self) -> impl Iterator<Item = (usize, &Base1Expr)> {
+        self.exprs.iter().enumerate().filter_map(|(i, r)| match r.expr() {
+            Expr::Base1(e) => Some((i, e)),
+            _ => None,
+        })
+    }
+
+    /// K of the selected category, with `Strategy::CollectAll` the first matched one
+    pub fn run(&self, s: &Scope) -> Result<f64> {
+        Ok(self.run_all(s)?[0].1)
+    }
+
+    /// Categories selected by the strategy together with their K, never empty
+    pub fn run_all(&self, s: &Scope) -> Result<Vec<(output::H, f64)>> {
+        let matched = self.matching(s, |_, _, _, _| {})?;
+        self.select(&matched)?.into_iter()
+            .map(|(i, _)| {
+                let h = self.exprs[i].expr().category();
+                Ok((h.clone(), self.formula(h)?.1.run(s)?))
+            })
+            .collect()
     }
 
-    pub fn run(&self, s: Scope) -> Result<f64> {
-        let rs: Vec<output::H> = self.exprs.iter().filter_map(
-            |x| match x {
-                Expr::Base1(e) => e.run(s),
-                _ => None
+    /// Step by step account of `run_all` for the scope, an error stops the
+    /// evaluation and is recorded in the trace
+    pub fn explain(&self, s: &Scope) -> Explanation {
+        let mut explanation = Explanation {
+            strategy: self.strategy,
+            rules: vec![],
+            selected: vec![],
+            error: None,
+        };
+        if let Err(e) = self.trace(s, &mut explanation) {
+            explanation.error = Some(e.to_string());
+        }
+        explanation
+    }
+
+    fn trace(&self, s: &Scope, explanation: &mut Explanation) -> Result<()> {
+        let matched = self.matching(s, |i, e, hit, error| explanation.rules.push(RuleTrace {
+            rule: RuleRef::new(i, e),
+            priority: self.exprs[i].priority(),
+            matched: hit,
+            error: error.map(|e| e.to_string()),
+        }))?;
+
+        for (i, reason) in self.select(&matched)? {
+            let h = self.exprs[i].expr().category();
+            let mut selection = Selection {
+                rule: i,
+                category: h.to_string(),
+                reason,
+                formula: None,
+            };
+            let value = self.formula(h).and_then(|(j, f)| {
+                let mut steps = vec![];
+                let value = f.trace(s, &mut steps);
+                selection.formula = Some(FormulaTrace {
+                    index: j,
+                    formula: f.formula().to_string(),
+                    steps,
+                    value: value.as_ref().ok().cloned(),
+                });
+                value
+            });
+            explanation.selected.push(selection);
+            value?;
+        }
+        Ok(())
+    }
+
+    /// Base1 rules whose condition holds, `visit` sees every rule tested. A condition which can
+    /// not be evaluated, e.g. testing an input the scope does not have, is visited with the error
+    /// and ends the evaluation
+    fn matching<F>(&self, s: &Scope, mut visit: F) -> Result<Vec<usize>>
+        where F: FnMut(usize, &Base1Expr, bool, Option<&anyhow::Error>) {
+        let mut matched = vec![];
+        for (i, e) in self.base1() {
+            let hit = match e.run(s) {
+                Ok(hit) => hit.is_some(),
+                Err(err) => {
+                    visit(i, e, false, Some(&err));
+                    return Err(err);
+                },
+            };
+            visit(i, e, hit, None);
+            if hit {
+                matched.push(i);
             }
-        ).collect();
+        }
+        Ok(matched)
+    }
 
-        if rs.is_empty() {
+    /// Positions of the rules the strategy picks among the matched ones, with the reason
+    fn select(&self, matched: &[usize]) -> Result<Vec<(usize, String)>> {
+        if matched.is_empty() {
             return Err(anyhow!("expr not found"))
         }
-        let fst = rs[0];
-        for i in &self.exprs {
-            match i {
+
+        let category = |i: usize| self.exprs[i].expr().category();
+        match self.strategy {
+            Strategy::FirstMatch => Ok(vec![(matched[0], "first matching rule".to_string())]),
+            Strategy::HighestPriority => {
+                let mut best = matched[0];
+                for &i in &matched[1..] {
+                    if self.exprs[i].priority() > self.exprs[best].priority() {
+                        best = i;
+                    }
+                }
+                let reason = format!("highest priority among matching rules ({})", self.exprs[best].priority());
+                Ok(vec![(best, reason)])
+            },
+            Strategy::ErrorOnAmbiguity | Strategy::CollectAll => {
+                let mut selected: Vec<usize> = vec![];
+                for &i in matched {
+                    if !selected.iter().any(|&j| category(j) == category(i)) {
+                        selected.push(i);
+                    }
+                }
+                if self.strategy == Strategy::ErrorOnAmbiguity && selected.len() > 1 {
+                    let names: Vec<&str> = selected.iter().map(|&i| category(i).name()).collect();
+                    return Err(anyhow!("ambiguous match, rules produce {}", names.join(", ")));
+                }
+                Ok(selected.into_iter().map(|i| {
+                    let reason = match self.strategy {
+                        Strategy::CollectAll => format!("first matching rule of category {}", category(i)),
+                        _ => format!("every matching rule produces {}", category(i)),
+                    };
+                    (i, reason)
+                }).collect())
+            },
+        }
+    }
+
+    /// Base2 rule for the category with its position among all rules
+    fn formula(&self, h: &output::H) -> Result<(usize, &Base2Expr)> {
+        for (i, r) in self.exprs.iter().enumerate() {
+            match r.expr() {
                 Expr::Base2(e) => {
-                    if e.output() == fst {
-                        return e.run(s)
+                    if e.output() == h {
+                        return Ok((i, e))
                     }
                 },
                 _ => continue,
             }
         }
-        return Err(anyhow!("expr for {:?} not found", fst));
+        Err(anyhow!("expr for {} not found", h))
     }
 }
 
@@ -76,11 +680,17 @@ impl App {
 #[cfg(test)]
 mod test {
     use crate::lib::scope::Scope;
-    use super::App;
+    use crate::lib::functions::Arity;
+    use crate::lib::output;
+    use crate::lib::scope::Value;
+    use crate::lib::expr::Expr;
+    use crate::lib::tokenize::Span;
+    use crate::lib::rule::RuleSource;
+    use super::{App, Strategy};
 
     #[test]
     fn test_app() {
-        let app = App::new();
+        let app = App::new().category("M").category("P").category("T");
         let app = app.add("A && B && !C => H = M").unwrap();
         let app = app.add("A && B && C => H = P").unwrap();
         let app = app.add("!A && B && C => H = T").unwrap();
@@ -88,10 +698,10 @@ mod test {
         let app = app.add("H = P => K = D + (D * (E - F) / 25.5)").unwrap();
         let app = app.add("H = T => K = D - (D * F / 30)").unwrap();
 
-        let rs = app.run(Scope::new(false, true, true, 5.0, 0, 30)).unwrap();
+        let rs = app.run(&Scope::new(false, true, true, 5.0, 0, 30)).unwrap();
         assert_eq!(0.0, rs);
 
-        let rs = app.run(Scope::new(true, true, true, 1.0, 52, 1)).unwrap();
+        let rs = app.run(&Scope::new(true, true, true, 1.0, 52, 1)).unwrap();
         assert_eq!(3.0, rs);
 
         assert!(app.add("1").is_err());
@@ -99,7 +709,472 @@ mod test {
         assert_eq!(3.0,
                    app.add("A && B && !C => H = P").
                        unwrap().
-                       run(Scope::new(true, true, false, 1.0, 52, 1)).
+                       run(&Scope::new(true, true, false, 1.0, 52, 1)).
                        unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_named_variables() {
+        let app = App::new()
+            .category("M")
+            .add("is_vip && !is_blocked => H = M").unwrap()
+            .add("H = M => K = order_total - order_total * 0.1 * items_count").unwrap();
+
+        let s = Scope::default()
+            .set("is_vip", true)
+            .set("is_blocked", false)
+            .set("order_total", 100.0)
+            .set("items_count", 2);
+        assert_eq!(80.0, app.run(&s).unwrap());
+
+        let s = Scope::default().set("is_vip", true).set("is_blocked", false);
+        assert_eq!("unknown variable `order_total`", app.run(&s).unwrap_err().to_string());
+
+        let s = Scope::default().set("is_vip", 1).set("is_blocked", false);
+        assert_eq!("variable `is_vip` is int, expected bool", app.run(&s).unwrap_err().to_string());
+
+        // an input missing from the scope is an error even when another rule matches
+        let app = App::default().add("is_vip => H = M").unwrap();
+        let s = Scope::default().set("is_vip", true).set("D", 10.0).set("E", 5);
+        assert_eq!("unknown variable `A`", app.run(&s).unwrap_err().to_string());
+        let s = s.set("A", false).set("B", false).set("C", false);
+        assert_eq!(15.0, app.run(&s).unwrap());
+        let app = App::default().with_strategy(Strategy::HighestPriority)
+            .add_with_priority("is_vip => H = P", 1).unwrap()
+            .add_with_priority("order_total > 100 => H = T", 2).unwrap();
+        let s = s.set("is_vip", true);
+        assert_eq!("unknown variable `order_total`", app.run(&s).unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_categories() {
+        let app = App::new()
+            .category("GOLD")
+            .category("SILVER")
+            .category("REJECT")
+            .add("is_vip && order_total_big => H = GOLD").unwrap()
+            .add("is_vip && !order_total_big => H = SILVER").unwrap()
+            .add("!is_vip => H = REJECT").unwrap()
+            .add("H = GOLD => K = 20").unwrap()
+            .add("H = SILVER => K = 10").unwrap()
+            .add("H = REJECT => K = 0").unwrap();
+        assert!(app.validate().is_ok());
+
+        let s = Scope::default().set("is_vip", true).set("order_total_big", false);
+        assert_eq!(10.0, app.run(&s).unwrap());
+        let s = Scope::default().set("is_vip", false).set("order_total_big", true);
+        assert_eq!(0.0, app.run(&s).unwrap());
+
+        assert_eq!("category BRONZE is not declared",
+                   app.add("H = BRONZE => K = 5").unwrap_err().to_string());
+        assert_eq!("category BRONZE is not declared",
+                   app.add("is_vip => H = BRONZE").unwrap_err().to_string());
+
+        let app = app.category("BRONZE").add("H = BRONZE => K = 5").unwrap();
+        assert_eq!("category BRONZE is not produced by any rule", app.validate().unwrap_err().to_string());
+
+        let app = App::new().category("GOLD").add("is_vip => H = GOLD").unwrap();
+        let s = Scope::default().set("is_vip", true);
+        assert_eq!("expr for GOLD not found", app.run(&s).unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_same_formula_categories() {
+        let app = App::new()
+            .category("M")
+            .category("P")
+            .add("A => H = M").unwrap()
+            .add("!A => H = P").unwrap()
+            .add("H = M => K = D").unwrap()
+            .add("H = P => K = D").unwrap();
+        assert_eq!(4, app.rules().len());
+        assert!(app.validate().is_ok());
+        assert_eq!(2.0, app.run(&Scope::new(true, false, false, 2.0, 0, 0)).unwrap());
+        assert_eq!(3.0, app.run(&Scope::new(false, false, false, 3.0, 0, 0)).unwrap());
+
+        let app = app.add("H = P => K = D").unwrap();
+        assert_eq!(4, app.rules().len());
+    }
+
+    #[test]
+    fn test_custom_functions() {
+        let app = App::new()
+            .category("M")
+            .register_function("tax", Arity::Exact(2), |args| Ok(args[0] * args[1] / 100.0)).unwrap()
+            .register_function("discount_tier", Arity::Exact(1), |args| Ok(if args[0] >= 10.0 { 2.0 } else { 1.0 })).unwrap();
+
+        assert_eq!("unknown function vat", app.add("H = M => K = vat(D)").unwrap_err().to_string());
+        assert_eq!("function tax expects 2 arguments, found 1", app.add("H = M => K = tax(D)").unwrap_err().to_string());
+
+        let app = app
+            .add("discount_tier(E) > 1 => H = M").unwrap()
+            .add("H = M => K = D + tax(D, 20) - discount_tier(E)").unwrap();
+
+        let cloned = app.clone();
+        assert_eq!(app, cloned);
+        let handle = std::thread::spawn(move || cloned.run(&Scope::def(100.0, 12, 0)).unwrap());
+        assert_eq!(118.0, handle.join().unwrap());
+        assert!(app.run(&Scope::def(100.0, 2, 0)).is_err());
+
+        assert!(App::new().register_function("max", Arity::Exact(1), |args| Ok(args[0])).is_err());
+    }
+
+    #[test]
+    fn test_strategies() {
+        let app = App::new()
+            .category("M")
+            .category("P")
+            .category("T")
+            .add_with_priority("A && B => H = M", 1).unwrap()
+            .add_with_priority("A && !C => H = P", 5).unwrap()
+            .add_with_priority("A => H = M", 5).unwrap()
+            .add("H = M => K = 1").unwrap()
+            .add("H = P => K = 2").unwrap();
+        let both = Scope::abc(true, true, false);
+        let only_m = Scope::abc(true, true, true);
+        let h = |name: &str| output::H::new(name);
+
+        assert_eq!(Strategy::FirstMatch, app.strategy());
+        assert_eq!(1.0, app.run(&both).unwrap());
+
+        let app = app.with_strategy(Strategy::HighestPriority);
+        assert_eq!(2.0, app.run(&both).unwrap());
+        assert_eq!(1.0, app.run(&only_m).unwrap());
+
+        let app = app.with_strategy(Strategy::ErrorOnAmbiguity);
+        assert_eq!("ambiguous match, rules produce M, P", app.run(&both).unwrap_err().to_string());
+        assert_eq!(vec![(h("M"), 1.0)], app.run_all(&only_m).unwrap());
+
+        let app = app.with_strategy(Strategy::CollectAll);
+        assert_eq!(vec![(h("M"), 1.0), (h("P"), 2.0)], app.run_all(&both).unwrap());
+        assert_eq!(1.0, app.run(&both).unwrap());
+        assert_eq!("expr not found", app.run_all(&Scope::abc(false, true, true)).unwrap_err().to_string());
+
+        let app = app.add("!A => H = T").unwrap();
+        assert_eq!("expr for T not found", app.run_all(&Scope::abc(false, true, true)).unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_add_all() {
+        let app = App::default().add_all(&["A => H = M", "H = M => K = D"]).unwrap();
+        assert_eq!(Ok(6.0), app.run(&Scope::new(true, false, false, 6.0, 0, 0)).map_err(|e| e.to_string()));
+
+        let errors = App::default().add_all(&[
+            "A && => H = M",
+            "B => H = P",
+            "C => H = GOLD",
+            "H = P => K = D ~ 2",
+        ]).unwrap_err();
+        let found: Vec<(usize, String)> = errors.iter().map(|e| (e.rule, e.message.clone())).collect();
+        assert_eq!(vec![
+            (0, "unexpected `=>`, expected identifier, number, `(`, `-`, `+` or `!`".to_string()),
+            (2, "category GOLD is not declared".to_string()),
+            (3, "invalid character `~`".to_string()),
+        ], found);
+        assert_eq!(Some(Span::new(5, 7)), errors[0].syntax.as_ref().map(|se| se.span));
+        assert_eq!(None, errors[1].syntax);
+    }
+
+    #[test]
+    fn test_rule_set() {
+        let app = App::default()
+            .with_strategy(Strategy::HighestPriority)
+            .add_with_priority("A  &&  !B => H = T", 5).unwrap();
+        let set = app.rule_set();
+        assert_eq!(vec!["M", "P", "T"], set.categories);
+        assert_eq!(7, set.rules.len());
+        assert_eq!(("A  &&  !B => H = T", 5), (set.rules[6].source.as_str(), set.rules[6].priority));
+
+        // int inputs belong to the base like the functions
+        let loaded = App::new().int_input("E").int_input("F").with_rule_set(&set).unwrap();
+        assert_eq!(app, loaded);
+        assert_eq!(set, loaded.rule_set());
+
+        let app = app.remove("A && !B => H = T").unwrap();
+        assert_eq!(6, app.rule_set().rules.len());
+        assert_eq!("no rule matches `A && !B => H = T`", app.remove("A && !B => H = T").unwrap_err().to_string());
+
+        let mut set = App::default().rule_set();
+        set.categories.pop();
+        let errors = App::new().with_rule_set(&set).unwrap_err();
+        let found: Vec<(usize, String)> = errors.into_iter().map(|e| (e.rule, e.message)).collect();
+        assert_eq!(vec![(2, "category T is not declared".to_string()), (5, "category T is not declared".to_string())], found);
+    }
+
+    #[test]
+    fn test_rule_set_unproduced() {
+        let mut set = App::default().rule_set();
+        set.rules.remove(2);
+        let errors = App::new().with_rule_set(&set).unwrap_err();
+        let found: Vec<(usize, String)> = errors.into_iter().map(|e| (e.rule, e.message)).collect();
+        assert_eq!(vec![(4, "category T is not produced by any rule".to_string())], found);
+    }
+
+    #[test]
+    fn test_rule_ids() {
+        let app = App::default();
+        let ids: Vec<u64> = app.rules().iter().map(|r| r.id()).collect();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], ids);
+
+        // same program replaces the rule and keeps its id
+        let app = app.add("A&&B && !C =>  H = M").unwrap();
+        assert_eq!(1, app.rules()[5].id());
+        assert_eq!("A&&B && !C =>  H = M", app.rule(1).unwrap().source());
+
+        let src = RuleSource {
+            id: None,
+            name: Some("vip".to_string()),
+            owner: None,
+            description: Some("vip customers".to_string()),
+            source: "A && !B => H = P".to_string(),
+            priority: 0,
+        };
+        let app = app.put_rule(2, app.parse_rule(&src).unwrap()).unwrap();
+        assert_eq!(Some("vip"), app.rule(2).map(|r| r.name().unwrap()));
+        assert_eq!(2, app.rules()[0].id());
+        assert_eq!("rule 2 has the same expression",
+                   app.put_rule(9, app.parse_rule(&src).unwrap()).unwrap_err().to_string());
+
+        let app = app.put_rule(20, app.parse_rule(&RuleSource { source: "C => H = P".to_string(), ..src }).unwrap()).unwrap();
+        let app = app.remove_rule(20).unwrap();
+        assert_eq!("rule 20 not found", app.remove_rule(20).unwrap_err().to_string());
+        assert_eq!(21, app.add("B => H = P").unwrap().rule_set().rules[6].id.unwrap());
+
+        let loaded = App::default().with_rule_set(&app.rule_set()).unwrap();
+        assert_eq!(app, loaded);
+    }
+
+    #[test]
+    fn test_from_source() {
+        let app = App::from_source("categories M\n\n# only A\nA\n  => H = M\nH = M => K = D * 2\n").unwrap();
+        assert_eq!(5.0, app.run(&Scope::new(true, false, false, 2.5, 0, 0)).unwrap());
+        assert_eq!("A\n  => H = M", app.rule(1).unwrap().source());
+
+        let source = "categories M\nA &&\n  => H = M\nB => H = X\n";
+        assert_eq!("error: unexpected `=>`, expected identifier, number, `(`, `-`, `+` or `!`\n --> rules.sprout:3:3\n  |\n3 |   => H = M\n  |   ^^\n\
+                    error: category X is not declared\n --> rules.sprout:4:1",
+                   App::new().load_source(source, "rules.sprout:").unwrap_err().to_string());
+        assert_eq!("error: invalid character `~`\n --> rules.sprout:2:16\n  |\n2 | H = M => K = D ~ 2\n  |                ^",
+                   App::new().load_source("categories M\nH = M => K = D ~ 2", "rules.sprout:").unwrap_err().to_string());
+
+        let path = std::env::temp_dir().join(format!("sprout-app-{}.sprout", std::process::id()));
+        std::fs::write(&path, "categories M\nrule \"a\" {\n  priority 3\n  A => H = M\n}\n").unwrap();
+        let app = App::from_file(&path).unwrap();
+        assert_eq!((Some("a"), 3), (app.rule(1).unwrap().name(), app.rule(1).unwrap().priority()));
+        std::fs::write(&path, "categories M\nrule \"a\" {\n  A => H = M\n").unwrap();
+        assert_eq!(format!("error: unexpected end of input, expected `}}`\n --> {}:4:1\n  |\n4 | \n  | ^", path.display()),
+                   App::from_file(&path).unwrap_err().to_string());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_overlaps() {
+        assert!(App::default().overlaps().unwrap().is_empty());
+
+        let app = App::default()
+            .add("A && !C => H = P").unwrap()
+            .add("A && B => H = M").unwrap()
+            .add("D > 100 && !B => H = T").unwrap();
+        let overlaps = app.overlaps().unwrap();
+        let pairs: Vec<(usize, usize)> = overlaps.iter().map(|o| (o.first.index, o.second.index)).collect();
+        assert_eq!(vec![(0, 6), (1, 7), (6, 7), (6, 8)], pairs);
+
+        let o = &overlaps[0];
+        assert_eq!("A && B && !C", o.first.condition);
+        assert_eq!("P", o.second.category);
+        let w: Vec<(&str, Value)> = o.witness.inputs.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        assert_eq!(vec![("A", Value::Bool(true)), ("B", Value::Bool(true)), ("C", Value::Bool(false))], w);
+
+        let w = &overlaps[3].witness.inputs;
+        assert_eq!(Some(&Value::Int(101)), w.get("D"));
+        assert_eq!(Some(&Value::Bool(false)), w.get("B"));
+
+        // a comparison and its negation never hold together, E only takes whole numbers
+        let app = App::new().category("M").category("P")
+            .add("D + E > 3 => H = M").unwrap()
+            .add("3 >= D + E => H = P").unwrap()
+            .add("E > 2 && E < 3 => H = M").unwrap()
+            .add("E >= 2 => H = P").unwrap();
+        let pairs = |app: &App| -> Vec<(usize, usize)> {
+            app.overlaps().unwrap().iter().map(|o| (o.first.index, o.second.index)).collect()
+        };
+        assert_eq!(vec![(2, 3)], pairs(&app));
+        assert_eq!(Some(&Value::Float(2.5)), app.overlaps().unwrap()[0].witness.inputs.get("E"));
+        assert!(pairs(&app.int_input("E")).is_empty());
+    }
+
+    #[test]
+    fn test_coverage() {
+        let coverage = App::default().coverage().unwrap();
+        let gaps: Vec<&Vec<String>> = coverage.gaps.iter().map(|g| &g.literals).collect();
+        assert_eq!(vec![&vec!["!B".to_string()], &vec!["!A".to_string(), "!C".to_string()]], gaps);
+        assert_eq!(Some(&Value::Bool(false)), coverage.gaps[0].example.inputs.get("B"));
+        assert!(coverage.missing_formulas.is_empty());
+        assert!(!coverage.is_complete());
+
+        let app = App::default()
+            .category("X")
+            .add("!B => H = X").unwrap()
+            .add("!A && !C => H = M").unwrap();
+        let coverage = app.coverage().unwrap();
+        assert!(coverage.gaps.is_empty());
+        assert_eq!(vec!["X".to_string()], coverage.missing_formulas);
+
+        let app = app.add("H = X => K = D").unwrap();
+        assert!(app.coverage().unwrap().is_complete());
+    }
+
+    #[test]
+    fn test_lint() {
+        assert!(App::default().lint().unwrap().is_empty());
+
+        let app = App::default()
+            .category("X")
+            .add("A && !A => H = T").unwrap()
+            .add("A && B && !C && D > 1 => H = T").unwrap()
+            .add("B && C => H = M").unwrap()
+            .add("H = X => K = 1").unwrap();
+        let messages: Vec<String> = app.lint().unwrap().iter().map(|d| d.to_string()).collect();
+        assert_eq!(vec![
+            "rule 6 `A && !A => H = T` can never match, the condition is unsatisfiable",
+            "rule 7 `A && B && !C && D > 1 => H = T` can never fire, it is shadowed by rule 0",
+            "rule 8 `B && C => H = M` can never fire, it is shadowed by rules 1, 2",
+            "formula 9 for category X is unreachable, no rule produces X",
+        ], messages);
+
+        let app = app.with_strategy(Strategy::CollectAll);
+        assert_eq!(2, app.lint().unwrap().len());
+
+        let app = App::default()
+            .with_strategy(Strategy::HighestPriority)
+            .add_with_priority("A && B => H = T", 1).unwrap();
+        let messages: Vec<String> = app.lint().unwrap().iter().map(|d| d.to_string()).collect();
+        assert_eq!(vec!["rule 0 `A && B && !C => H = M` can never fire, it is shadowed by rule 6",
+                        "rule 1 `A && B && C => H = P` can never fire, it is shadowed by rule 6"], messages);
+
+        // rules over other inputs still shadow
+        let app = App::new().category("M").category("P")
+            .add("X || !X => H = M").unwrap()
+            .add("A => H = P").unwrap();
+        let messages: Vec<String> = app.lint().unwrap().iter().map(|d| d.to_string()).collect();
+        assert_eq!(vec!["rule 1 `A => H = P` can never fire, it is shadowed by rule 0"], messages);
+        let app = App::new().category("M").category("P")
+            .add("X => H = M").unwrap()
+            .add("!X && A => H = P").unwrap()
+            .add("A => H = M").unwrap();
+        let messages: Vec<String> = app.lint().unwrap().iter().map(|d| d.to_string()).collect();
+        assert_eq!(vec!["rule 2 `A => H = M` can never fire, it is shadowed by rules 0, 1"], messages);
+
+        // a rule whose check has too many atoms and a rule too large to check
+        let app = (0..9).fold(App::default(), |app, i| app.add(&format!("X{} && Y{} => H = T", i, i)).unwrap());
+        let vars: Vec<String> = (0..21).map(|i| format!("Z{}", i)).collect();
+        let app = app.add(&format!("{} => H = T", vars.join(" && "))).unwrap();
+        let messages: Vec<String> = app.lint().unwrap().iter().map(|d| d.to_string()).collect();
+        assert_eq!(vec![
+            "rule 14 `X8 && Y8 => H = T` was not checked, too many conditions to analyze, found 21 atoms, at most 20".to_string(),
+            format!("rule 15 `{} => H = T` was not checked, too many conditions to analyze, found 21 atoms, at most 20", vars.join(" && ")),
+        ], messages);
+    }
+
+    #[test]
+    fn test_explain() {
+        let app = App::default();
+        let ex = app.explain(&Scope::new(true, true, true, 1.0, 52, 1));
+        assert_eq!(None, ex.error);
+        let matched: Vec<(usize, bool)> = ex.rules.iter().map(|r| (r.rule.index, r.matched)).collect();
+        assert_eq!(vec![(0, false), (1, true), (2, false)], matched);
+
+        let selected = &ex.selected[0];
+        assert_eq!((1, "P", "first matching rule"), (selected.rule, selected.category.as_str(), selected.reason.as_str()));
+        let formula = selected.formula.as_ref().unwrap();
+        assert_eq!((4, "D + D * (E - F) / 25.5", Some(3.0)), (formula.index, formula.formula.as_str(), formula.value));
+        let steps: Vec<(&str, &[f64])> = formula.steps.iter().map(|s| (s.op.as_str(), s.stack.as_slice())).collect();
+        assert_eq!(vec![
+            ("load D", &[1.0][..]),
+            ("load D", &[1.0, 1.0][..]),
+            ("load E", &[1.0, 1.0, 52.0][..]),
+            ("load F", &[1.0, 1.0, 52.0, 1.0][..]),
+            ("sub", &[1.0, 1.0, 51.0][..]),
+            ("mul", &[1.0, 51.0][..]),
+            ("push 25.5", &[1.0, 51.0, 25.5][..]),
+            ("div", &[1.0, 2.0][..]),
+            ("add", &[3.0][..]),
+        ], steps);
+
+        let ex = app.explain(&Scope::new(false, false, false, 1.0, 52, 1));
+        assert_eq!(Some("expr not found".to_string()), ex.error);
+        assert_eq!(3, ex.rules.len());
+        assert!(ex.selected.is_empty());
+
+        let app = App::new()
+            .category("X")
+            .category("Y")
+            .with_strategy(Strategy::HighestPriority)
+            .add("A && B => H = Y").unwrap()
+            .add_with_priority("A => H = X", 2).unwrap()
+            .add("H = X => K = D / -F").unwrap();
+        let ex = app.explain(&Scope::new(true, true, true, 1.0, 52, 0));
+        assert_eq!("highest priority among matching rules (2)", ex.selected[0].reason);
+        assert_eq!(Some("Zero division".to_string()), ex.error);
+        let formula = ex.selected[0].formula.as_ref().unwrap();
+        assert_eq!((2, None), (formula.index, formula.value));
+        let steps: Vec<&str> = formula.steps.iter().map(|s| s.op.as_str()).collect();
+        assert_eq!(vec!["load D", "load F", "neg"], steps);
+
+        // a rule whose condition fails is traced with its error, which ends the trace
+        let app = App::default().add("is_vip => H = M").unwrap();
+        let ex = app.explain(&Scope::default().set("is_vip", true).set("A", false).set("D", 1.0).set("E", 10));
+        let rules: Vec<(usize, bool, Option<&str>)> = ex.rules.iter().map(|r| (r.rule.index, r.matched, r.error.as_deref())).collect();
+        assert_eq!(vec![(0, false, Some("unknown variable `B`"))], rules);
+        assert_eq!(Some("unknown variable `B`".to_string()), ex.error);
+        assert!(ex.selected.is_empty());
+
+        let ex = app.explain(&Scope::default().set("A", 1));
+        assert_eq!(1, ex.rules.len());
+        assert_eq!(ex.error, ex.rules[0].error);
+        assert_eq!(Some("variable `A` is int, expected bool".to_string()), ex.error);
+    }
+
+    /// Random token sequences must come back as errors, never as panics
+    #[test]
+    fn test_fuzz() {
+        let words = [
+            "A", "B", "D", "E", "x", "H", "K", "M", "P", "=", "=>", "==", "!=", "!", "&&", "||", "<", ">=",
+            "+", "-", "*", "/", "(", ")", ",", "0", "1", "2.5", "max", "sqrt", "round", "clamp", "@",
+        ];
+        let mut state: u64 = 0x9E3779B97F4A7C15;
+        let mut next = |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+        let app = App::new()
+            .category("M")
+            .category("P")
+            .with_strategy(Strategy::CollectAll)
+            .add("A => H = M").unwrap()
+            .add("H = P => K = D").unwrap();
+        let scope = Scope::new(true, false, true, 2.5, 0, -1).set("x", 4);
+        for _ in 0..20000 {
+            let mut s = match next(3) {
+                0 => "H = M => K = ".to_string(),
+                1 => String::new(),
+                _ => "A && ".to_string(),
+            };
+            for _ in 0..next(12) {
+                s.push_str(words[next(words.len())]);
+                s.push(' ');
+            }
+            if next(2) == 0 {
+                s.push_str("=> H = P");
+            }
+
+            let _ = Expr::from_str(&s);
+            if let Ok(app) = app.add(&s) {
+                let _ = app.run(&scope);
+                let _ = app.explain(&scope);
+            }
+        }
+    }
+}
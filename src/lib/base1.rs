@@ -1,14 +1,20 @@
 use anyhow::Result;
-use crate::lib::tokenize::Token;
-use std::fmt::Debug;
+use crate::lib::tokenize::{Token, Spanned};
+use crate::lib::error::SyntaxErrors;
+use std::fmt::{self, Debug};
 use crate::lib::output;
 use crate::lib::scope::Scope;
+use crate::lib::parser::{Parser, Node, LogicOp, CmpOp};
+use crate::lib::base2;
+use crate::lib::functions::Functions;
+use crate::lib::rpn::{self, pop, Instruction};
 
 
 #[derive(Debug, Clone)]
 pub struct Base1Expr {
     output: output::H,
-    original: Vec<Token>,
+    original: Vec<Spanned>,
+    condition: Node,
     executable: Executable,
 }
 
@@ -19,146 +25,345 @@ impl PartialEq for Base1Expr {
 }
 
 impl Base1Expr {
-    pub fn build(tokens: &[Token]) -> Result<Base1Expr> {
-        let pos = tokens.iter().position(|&x| x == Token::Arrow);
-        return match pos {
-            Some(p) => {
-                let (left, _right) = tokens.split_at(p);
-                let ex = Executable::build(left)?;
-                let out = match tokens.get(p + 3) {
-                    Some(Token::M) => output::H::M,
-                    Some(Token::P) => output::H::P,
-                    Some(Token::T) => output::H::T,
-                    _ => return Err(anyhow!("no output found")),
-                };
-                Ok(Base1Expr {
-                    output: out,
-                    original: tokens.to_vec(),
-                    executable: ex,
-                })
+    pub fn output(&self) -> &output::H {
+        &self.output
+    }
+
+    /// Parsed condition left of the arrow, used by the static analysis
+    pub fn condition(&self) -> &Node {
+        &self.condition
+    }
+
+    /// Expects `<condition> => H = <category>`, after an error in the condition parsing
+    /// resumes behind `=>` so the category part is reported as well
+    pub fn build(tokens: &[Spanned], fns: &Functions) -> Result<Base1Expr> {
+        let mut p = Parser::new(tokens);
+        let mut errors = vec![];
+        let condition = match p.condition() {
+            Ok(c) if p.expect(&Token::Arrow).is_ok() => Some(c),
+            Ok(_) => {
+                errors.push(p.unexpected(&["operator", "`=>`"]));
+                None
+            },
+            Err(e) => {
+                errors.push(e);
+                None
             },
-            None => Err(anyhow!("invalid expression"))
+        };
+        if condition.is_none() && !p.skip_past(&Token::Arrow) {
+            return Err(SyntaxErrors(errors).into());
+        }
+        let out = p.expect(&Token::H)
+            .and_then(|_| p.expect(&Token::Eq))
+            .and_then(|_| p.ident("category name"))
+            .and_then(|name| p.finish(&["end of input"]).map(|_| name));
+        let (condition, out) = match (condition, out) {
+            (Some(condition), Ok(name)) => (condition, output::H::new(&name)),
+            (_, out) => {
+                errors.extend(out.err());
+                return Err(SyntaxErrors(errors).into());
+            },
+        };
+
+        let ex = Executable::from_node(&condition, fns)?;
+        Ok(Base1Expr {
+            output: out,
+            original: tokens.to_vec(),
+            condition,
+            executable: ex,
+        })
+    }
+
+    pub fn run(&self, s: &Scope) -> Result<Option<output::H>> {
+        if self.executable.run(s)? {
+            return Ok(Some(self.output.clone()));
+        }
+        Ok(None)
+    }
+}
+
+/// Canonical source of the rule, the condition with minimal parentheses
+impl fmt::Display for Base1Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} => H = {}", self.condition, self.output)
+    }
+}
+
+/// Instruction of the reverse polish program
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Load(String),
+    Not,
+    And,
+    Or,
+    Compare(CmpOp, base2::Executable, base2::Executable),
+}
+
+impl Instruction for Op {
+    fn effect(&self) -> (usize, usize) {
+        match self {
+            Op::Load(_) | Op::Compare(..) => (0, 1),
+            Op::Not => (1, 1),
+            Op::And | Op::Or => (2, 1),
         }
     }
+}
 
-    pub fn run(&self, s: Scope) -> Option<output::H> {
-        if self.executable.run(s) {
-            return Some(self.output);
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Load(name) => write!(f, "load {}", name),
+            Op::Not => f.write_str("not"),
+            Op::And => f.write_str("and"),
+            Op::Or => f.write_str("or"),
+            Op::Compare(..) => f.write_str("compare"),
         }
-        None
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 struct Executable {
-    inner: Vec<Token>,
+    inner: Vec<Op>,
 }
 
 impl Executable {
-    fn run(&self, s: Scope) -> bool {
+    fn run(&self, s: &Scope) -> Result<bool> {
         let mut stack = vec![];
         for i in &self.inner {
             match i {
-                Token::A => stack.push(s.a),
-                Token::B => stack.push(s.b),
-                Token::C => stack.push(s.c),
-                Token::Not => {
-                    let b = !stack.pop().unwrap();
+                Op::Load(name) => stack.push(s.bool(name)?),
+                Op::Compare(op, l, r) => stack.push(op.apply(l.run(s)?, r.run(s)?)),
+                Op::Not => {
+                    let b = !pop(&mut stack)?;
                     stack.push(b);
                 },
-                Token::And => {
-                    let a = stack.pop().unwrap();
-                    let b = stack.pop().unwrap();
+                Op::And => {
+                    let a = pop(&mut stack)?;
+                    let b = pop(&mut stack)?;
                     stack.push(a && b);
                 },
-                _ => unreachable!()
+                Op::Or => {
+                    let a = pop(&mut stack)?;
+                    let b = pop(&mut stack)?;
+                    stack.push(a || b);
+                },
             }
         }
-        return stack.pop().unwrap()
+        rpn::result(stack)
     }
 
-    fn build(input: &[Token]) -> Result<Executable> {
-        let mut stack = vec![];
+    fn from_node(node: &Node, fns: &Functions) -> Result<Executable> {
         let mut out = vec![];
+        Executable::compile(node, fns, &mut out)?;
+        let exec = Executable {
+            inner: out
+        };
+        exec.verify()?;
+        Ok(exec)
+    }
 
-        for i in input {
-            match i {
-                Token::Not => stack.push(Token::Not),
-                Token::A => {
-                    out.push(Token::A);
-                    match stack.pop() {
-                        Some(v) => out.push(v),
-                        None => continue
-                    }
-                },
-                Token::B => {
-                    out.push(Token::B);
-                    match stack.pop() {
-                        Some(v) => out.push(v),
-                        None => continue
-                    }
-                },
-                Token::C => {
-                    out.push(Token::C);
-                    match stack.pop() {
-                        Some(v) => out.push(v),
-                        None => continue
-                    }
-                },
-                Token::And => {
-                    stack.push(Token::And);
-                },
-                t => return Err(anyhow!("unexpected token {:?}", t))
-            }
-        }
-        for i in stack {
-            out.push(i)
+    /// Stack depth analysis, the numeric operands of comparisons are verified by base2
+    fn verify(&self) -> Result<()> {
+        rpn::verify(&self.inner)
+    }
+
+    /// Emits the condition in reverse polish notation, operands of comparisons are
+    /// compiled as base2 numeric programs
+    fn compile(node: &Node, fns: &Functions, out: &mut Vec<Op>) -> Result<()> {
+        match node {
+            Node::Var(name) => out.push(Op::Load(name.clone())),
+            Node::Not(n) => {
+                Executable::compile(n, fns, out)?;
+                out.push(Op::Not);
+            },
+            Node::Logic(op, l, r) => {
+                Executable::compile(l, fns, out)?;
+                Executable::compile(r, fns, out)?;
+                out.push(match op {
+                    LogicOp::And => Op::And,
+                    LogicOp::Or => Op::Or,
+                });
+            },
+            Node::Compare(op, l, r) => {
+                let l = base2::Executable::from_node(l, fns)?;
+                let r = base2::Executable::from_node(r, fns)?;
+                out.push(Op::Compare(*op, l, r));
+            },
+            Node::Const(_) | Node::Unary(..) | Node::Binary(..) | Node::Call(..) => {
+                return Err(anyhow!("expected condition, found numeric expression"))
+            },
         }
-        return Ok(Executable {
-            inner: out
-        });
+        Ok(())
     }
 }
 
 
 #[cfg(test)]
 mod test {
-    use crate::lib::base1::{Base1Expr, Executable};
+    use anyhow::Result;
+    use crate::lib::functions::Functions;
+    use crate::lib::parser::Parser;
+    use crate::lib::base1::{Base1Expr, Executable, Op};
     use crate::lib::output;
     use crate::lib::scope::Scope;
-    use crate::lib::tokenize::Token::Const;
+    use crate::lib::tokenize::{tokenize, Token};
+
+    /// Translates a string in reverse polish notation to the program
+    fn ops(rpn: &str) -> Vec<Op> {
+        tokenize(rpn).unwrap().into_iter().map(|t| match t.token {
+            Token::Ident(name) => Op::Load(name),
+            Token::Not => Op::Not,
+            Token::And => Op::And,
+            Token::Or => Op::Or,
+            t => panic!("unexpected token {:?}", t),
+        }).collect()
+    }
+
+    fn try_build(s: &str) -> Result<Executable> {
+        Executable::from_node(&Parser::parse(&tokenize(s)?)?, &Functions::default())
+    }
+
+    fn build(s: &str) -> Executable {
+        try_build(s).unwrap()
+    }
 
+    fn expr(s: &str) -> Result<Base1Expr> {
+        Base1Expr::build(&tokenize(s).unwrap(), &Functions::default())
+    }
 
     #[test]
     fn test_build_executable() {
-        use super::super::tokenize::Token::*;
-        let v = vec![A, And, B, And, Not, C];
-        let out = Executable::build(&v).unwrap();
+        assert_eq!(ops("A B && C ! &&"), build("A && B && !C").inner);
+        assert_eq!(ops("A B || C ! &&"), build("(A || B) && !C").inner);
+        assert_eq!(ops("A B C && ||"), build("A || B && C").inner);
+        assert_eq!(ops("A B && !"), build("!(A && B)").inner);
 
-        assert_eq!(vec![A, B, And, C, Not, And], out.inner)
+        assert!(try_build("(A || B").is_err());
+        assert!(try_build("A || B)").is_err());
     }
 
     #[test]
-    fn test_base1_expr() {
-        use crate::lib::tokenize::Token::*;
-        let v = vec![A, And, B, And, Not, C, Arrow, H, Eq, M];
-        let rs1 = Base1Expr::build(&v).unwrap();
+    fn test_de_morgan() {
+        let equivalent = vec![
+            ("!(A && B)", "!A || !B"),
+            ("!(A || B)", "!A && !B"),
+            ("!(A && B && C)", "!A || !B || !C"),
+            ("!((A || B) && C)", "(!A && !B) || !C"),
+            ("A || B && C", "A || (B && C)"),
+            ("!A && B || C", "((!A) && B) || C"),
+        ];
+        for (l, r) in equivalent {
+            let l = try_build(l).unwrap();
+            let r = try_build(r).unwrap();
+            for i in 0..8 {
+                let s = Scope::abc(i & 1 != 0, i & 2 != 0, i & 4 != 0);
+                assert_eq!(l.run(&s).unwrap(), r.run(&s).unwrap());
+            }
+        }
+    }
 
-        let v = vec![Not, A, And, B, And, C, Arrow, H, Eq, T];
-        let rs2 = Base1Expr::build(&v).unwrap();
+    #[test]
+    fn test_base1_expr() {
+        let rs1 = expr("A && B && !C => H = M").unwrap();
+        let rs2 = expr("!A && B && C => H = T").unwrap();
 
         assert_ne!(rs1, rs2);
         assert_eq!(rs1, rs1);
 
-        assert_eq!(None, rs1.run(Scope::abc(true, true, true)));
-        assert_eq!(Some(output::H::M), rs1.run(Scope::abc(true, true, false)));
+        assert_eq!(None, rs1.run(&Scope::abc(true, true, true)).unwrap());
+        assert_eq!(Some(output::H::new("M")), rs1.run(&Scope::abc(true, true, false)).unwrap());
+
+        assert_eq!(Some(output::H::new("T")), rs2.run(&Scope::abc(false, true, true)).unwrap());
+        assert_eq!(None, rs2.run(&Scope::abc(true, true, true)).unwrap());
+
+        let rs3 = expr("(A || B) && !C => H = P").unwrap();
+        assert_eq!(Some(output::H::new("P")), rs3.run(&Scope::abc(false, true, false)).unwrap());
+        assert_eq!(None, rs3.run(&Scope::abc(false, false, false)).unwrap());
+        assert_eq!(None, rs3.run(&Scope::abc(true, false, true)).unwrap());
 
-        assert_eq!(Some(output::H::T), rs2.run(Scope::abc(false, true, true)));
-        assert_eq!(None, rs2.run(Scope::abc(true, true, true)));
+        let rs4 = expr("A && !B => H = GOLD").unwrap();
+        assert_eq!(&output::H::new("GOLD"), rs4.output());
+        assert_eq!(Some(output::H::new("GOLD")), rs4.run(&Scope::abc(true, false, false)).unwrap());
+        assert_eq!("A && !B => H = GOLD", rs4.to_string());
+        assert_eq!("(A || B) && !C => H = P", expr("((A || B)) && (!C) => H = P").unwrap().to_string());
+    }
+
+    #[test]
+    fn test_named_variables() {
+        let rs = expr("is_vip && !is_blocked => H = M").unwrap();
+        let s = Scope::default().set("is_vip", true).set("is_blocked", false);
+        assert_eq!(Some(output::H::new("M")), rs.run(&s).unwrap());
+
+        let s = Scope::default().set("is_vip", true);
+        assert_eq!("unknown variable `is_blocked`", rs.run(&s).unwrap_err().to_string());
+
+        let s = Scope::default().set("is_vip", 1).set("is_blocked", false);
+        assert_eq!("variable `is_vip` is int, expected bool", rs.run(&s).unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let rs = build("A && D > 100 && E <= 3");
+        assert!(rs.run(&Scope::new(true, false, false, 100.5, 3, 0)).unwrap());
+        assert!(!rs.run(&Scope::new(true, false, false, 100.0, 3, 0)).unwrap());
+        assert!(!rs.run(&Scope::new(true, false, false, 100.5, 4, 0)).unwrap());
+        assert!(!rs.run(&Scope::new(false, false, false, 100.5, 3, 0)).unwrap());
+
+        let s = Scope::def(2.0, 3, 5);
+        let values = vec![
+            ("D < E", true),
+            ("D >= E", false),
+            ("D + 1 == E", true),
+            ("(D + 1) * 2 != E * 2", false),
+            ("!(D > E) && F - E >= D", true),
+            ("D * -1 < 0 || E / F > 1", true),
+            ("max(D, E) >= round(F / 2)", true),
+        ];
+        for (e, expected) in values {
+            assert_eq!(expected, build(e).run(&s).unwrap(), "{}", e);
+        }
+
+        let rs = expr("order_total >= 100 && !is_blocked => H = M").unwrap();
+        let s = Scope::default().set("order_total", 150).set("is_blocked", false);
+        assert_eq!(Some(output::H::new("M")), rs.run(&s).unwrap());
     }
 
     #[test]
     fn test_invalid() {
-        assert!(Base1Expr::build(&vec![Const(1.0)]).is_err());
+        assert_eq!("expected condition, found numeric expression",
+                   try_build("A && D + 1").unwrap_err().to_string());
+        assert_eq!("expected numeric expression, found condition",
+                   try_build("D > (A && B)").unwrap_err().to_string());
+        assert!(try_build("D > E > F").is_err());
+        assert!(expr("1").is_err());
+        assert!(expr("A => H = 5").is_err());
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let err = |s: &str| expr(s).unwrap_err().to_string();
+        assert_eq!("unexpected identifier `B`, expected operator or `=>`", err("A B => H = M"));
+        assert_eq!("unexpected end of input, expected operator or `=>`", err("A && B"));
+        assert_eq!("unexpected end of input, expected category name", err("A && B => H ="));
+        assert_eq!("unexpected identifier `M`, expected end of input", err("A => H = M M"));
+        assert_eq!("unexpected `=>`, expected identifier, number, `(`, `-`, `+` or `!`\nunexpected end of input, expected category name",
+                   err("A && => H ="));
+        assert_eq!("unexpected identifier `B`, expected operator or `=>`\nunexpected `=`, expected `H`", err("A B => = M"));
+        assert_eq!("unexpected `)`, expected operator or `=>`", err("(A || B)) && C"));
+    }
+
+    #[test]
+    fn test_verify() {
+        let exec = |rpn: &str| Executable {
+            inner: ops(rpn),
+        };
+        assert!(exec("A B && C ! ||").verify().is_ok());
+        assert_eq!("invalid program: instruction 1 (and) needs 2 values, found 1",
+                   exec("A &&").verify().unwrap_err().to_string());
+        assert_eq!("invalid program: leaves 2 values, expected 1", exec("A B").verify().unwrap_err().to_string());
+
+        let s = Scope::abc(true, false, true);
+        assert_eq!("stack underflow", exec("!").run(&s).unwrap_err().to_string());
+        assert_eq!("1 values left on the stack", exec("A B").run(&s).unwrap_err().to_string());
     }
 }
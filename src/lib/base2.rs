@@ -1,194 +1,523 @@
-use crate::lib::tokenize::Token;
+use crate::lib::tokenize::{Token, Spanned};
+use crate::lib::error::SyntaxErrors;
 use crate::lib::scope::Scope;
 use anyhow::Result;
 use crate::lib::output;
+use crate::lib::parser::{Parser, Node, UnOp, BinOp};
+use crate::lib::functions::{Function, Functions};
+use crate::lib::trace::Step;
+use crate::lib::rpn::{self, pop, Instruction};
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct Base2Expr {
     output: output::H,
-    original: Vec<Token>,
+    original: Vec<Spanned>,
+    formula: Node,
     executable: Executable,
 }
 
+/// Formulas are the same when they compute the same program for the same category
 impl PartialEq for Base2Expr {
     fn eq(&self, other: &Self) -> bool {
-        self.executable == other.executable
+        self.output == other.output && self.executable == other.executable
     }
 }
 
 impl Base2Expr {
-    pub fn run(&self, s: Scope) -> Result<f64> {
+    pub fn run(&self, s: &Scope) -> Result<f64> {
         self.executable.run(s)
     }
 
-    pub fn build(tokens: &[Token]) -> Result<Base2Expr> {
-        let pos = tokens.iter().position(|&x| x == Token::Arrow);
-        if pos.is_none() {
-            return Err(anyhow!("Invalid tokens, no `Arrow` token found"));
+    /// Same as `run`, recording the stack after every instruction
+    pub fn trace(&self, s: &Scope, steps: &mut Vec<Step>) -> Result<f64> {
+        self.executable.exec(s, &mut Some(steps))
+    }
+
+    /// Parsed right side of `K = ...`
+    pub fn formula(&self) -> &Node {
+        &self.formula
+    }
+
+    /// Expects `H = <category> => K = <formula>`, after an error in the category part
+    /// parsing resumes behind `=>` so the formula is reported as well
+    pub fn build(tokens: &[Spanned], fns: &Functions) -> Result<Base2Expr> {
+        let mut p = Parser::new(tokens);
+        let mut errors = vec![];
+        let output = p.expect(&Token::H)
+            .and_then(|_| p.expect(&Token::Eq))
+            .and_then(|_| p.ident("category name"))
+            .and_then(|name| p.expect(&Token::Arrow).map(|_| name))
+            .map_err(|e| errors.push(e))
+            .ok();
+        if output.is_none() && !p.skip_past(&Token::Arrow) {
+            return Err(SyntaxErrors(errors).into());
         }
-        let pos = pos.unwrap();
-        let (left, _right) = tokens.split_at(pos);
-        let output = match left.get(2) {
-            Some(Token::M) => output::H::M,
-            Some(Token::P) => output::H::P,
-            Some(Token::T) => output::H::T,
-            Some(t) => return Err(anyhow!("Invalid tokens, expected one on [M, P, T], found {:?} found", t)),
-            None => return Err(anyhow!("Invalid tokens, expected eof"))
+        let formula = p.expect(&Token::K)
+            .and_then(|_| p.expect(&Token::Eq))
+            .and_then(|_| p.formula())
+            .and_then(|formula| p.finish(&["operator", "end of input"]).map(|_| formula));
+        let (output, formula) = match (output, formula) {
+            (Some(name), Ok(formula)) => (output::H::new(&name), formula),
+            (_, formula) => {
+                errors.extend(formula.err());
+                return Err(SyntaxErrors(errors).into());
+            },
         };
 
-        match (tokens.get(pos + 1), tokens.get(pos + 2)) {
-            (Some(Token::K), Some(Token::Eq)) => {
-                let exec = Executable::build(&tokens[pos+3..])?;
-                Ok(Base2Expr {
-                    original: tokens.to_vec(),
-                    executable: exec,
-                    output,
-                })
-            },
-            _ => return Err(anyhow!("Invalid tokens"))
+        let exec = Executable::from_node(&formula, fns)?;
+        Ok(Base2Expr {
+            original: tokens.to_vec(),
+            formula,
+            executable: exec,
+            output,
+        })
+    }
+
+    pub fn output(&self) -> &output::H {
+        &self.output
+    }
+}
+
+/// Canonical source of the rule, the formula with minimal parentheses
+impl fmt::Display for Base2Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "H = {} => K = {}", self.output, self.formula)
+    }
+}
+
+/// Instruction of the reverse polish program
+#[derive(PartialEq, Debug, Clone)]
+enum Op {
+    Load(String),
+    Const(f64),
+    Neg,
+    Bin(BinOp),
+    Call(Function, usize),
+}
+
+impl Instruction for Op {
+    fn effect(&self) -> (usize, usize) {
+        match self {
+            Op::Load(_) | Op::Const(_) => (0, 1),
+            Op::Neg => (1, 1),
+            Op::Bin(_) => (2, 1),
+            Op::Call(_, argc) => (*argc, 1),
         }
     }
+}
 
-    pub fn output(&self) -> output::H {
-        self.output
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Load(name) => write!(f, "load {}", name),
+            Op::Const(v) => write!(f, "push {}", v),
+            Op::Neg => f.write_str("neg"),
+            Op::Bin(BinOp::Add) => f.write_str("add"),
+            Op::Bin(BinOp::Sub) => f.write_str("sub"),
+            Op::Bin(BinOp::Mul) => f.write_str("mul"),
+            Op::Bin(BinOp::Div) => f.write_str("div"),
+            Op::Call(func, argc) => write!(f, "call {}/{}", func.name(), argc),
+        }
     }
 }
 
+/// Numeric program, also used for the operands of comparisons in base1 conditions
 #[derive(PartialEq, Debug, Clone)]
-struct Executable {
-    inner: Vec<Token>,
+pub(crate) struct Executable {
+    inner: Vec<Op>,
 }
 
 impl Executable {
-    pub fn build(tokens: &[Token]) -> Result<Executable> {
-        let mut stack = vec![];
+    pub fn from_node(node: &Node, fns: &Functions) -> Result<Executable> {
         let mut out = vec![];
+        Executable::compile(node, fns, &mut out)?;
+        let exec = Executable {
+            inner: out,
+        };
+        exec.verify()?;
+        Ok(exec)
+    }
 
-        for t in tokens {
-            match t {
-                Token::D => {
-                    out.push(Token::D)
-                },
-                Token::E => out.push(Token::E),
-                Token::F => out.push(Token::F),
-                Token::Plus => stack.push(Token::Plus),
-                Token::Minus => stack.push(Token::Minus),
-                Token::Open => stack.push(Token::Open),
-                Token::Close => {
-                    while let Some(t) = stack.pop() {
-                        if t == Token::Open {
-                            break;
-                        }
-                        out.push(t);
-                    }
+    /// Calls match the arity of the function, then the stack depth analysis of `rpn::verify`
+    fn verify(&self) -> Result<()> {
+        for (pos, i) in self.inner.iter().enumerate() {
+            if let Op::Call(f, argc) = i {
+                if !f.arity().accepts(*argc) {
+                    return Err(anyhow!("invalid program: instruction {} ({}) but {} expects {}", pos, i, f.name(), f.arity()));
                 }
-                Token::Multiple => {
-                    stack.push(Token::Multiple)
-                }
-                Token::Divide => {
-                    stack.push(Token::Divide)
-                },
-                Token::Const(v) => {
-                    out.push(Token::Const(*v))
-                },
-                t => return Err(anyhow!("unexpected token {:?}", t))
             }
         }
+        rpn::verify(&self.inner)
+    }
 
-        while stack.len() > 0 {
-            out.push(stack.pop().unwrap());
+    /// Emits the tree in reverse polish notation
+    fn compile(node: &Node, fns: &Functions, out: &mut Vec<Op>) -> Result<()> {
+        match node {
+            Node::Var(name) => out.push(Op::Load(name.clone())),
+            Node::Const(v) => out.push(Op::Const(*v)),
+            Node::Unary(UnOp::Pos, n) => Executable::compile(n, fns, out)?,
+            Node::Unary(UnOp::Neg, n) => match **n {
+                Node::Const(v) => out.push(Op::Const(-v)),
+                _ => {
+                    Executable::compile(n, fns, out)?;
+                    out.push(Op::Neg);
+                },
+            },
+            Node::Binary(op, l, r) => {
+                Executable::compile(l, fns, out)?;
+                Executable::compile(r, fns, out)?;
+                out.push(Op::Bin(*op));
+            },
+            Node::Call(name, args) => {
+                let f = fns.resolve(name, args.len())?;
+                for a in args {
+                    Executable::compile(a, fns, out)?;
+                }
+                out.push(Op::Call(f, args.len()));
+            },
+            Node::Not(_) | Node::Logic(..) | Node::Compare(..) => {
+                return Err(anyhow!("expected numeric expression, found condition"))
+            },
         }
+        Ok(())
+    }
 
-        return Ok(Executable {
-            inner: out,
-        })
+    pub fn run(&self, s: &Scope) -> Result<f64> {
+        self.exec(s, &mut None)
     }
 
-    pub fn run(&self, s: Scope) -> Result<f64> {
+    fn exec(&self, s: &Scope, steps: &mut Option<&mut Vec<Step>>) -> Result<f64> {
         let mut stack = vec![];
-        for (i, t) in self.inner.iter().enumerate() {
-            match t {
-                Token::D => stack.push(s.d),
-                Token::E => stack.push(s.e as f64),
-                Token::F => stack.push(s.f as f64),
-                Token::Const(v) => stack.push(*v),
-                Token::Plus => {
-                    let first = stack.pop().unwrap();
-                    let second = stack.pop().unwrap();
-                    stack.push(first + second);
-                },
-                Token::Minus => {
-                    let second = stack.pop().unwrap();
-                    let first = stack.pop().unwrap();
-                    stack.push(first - second);
+        for i in &self.inner {
+            match i {
+                Op::Load(name) => stack.push(s.number(name)?),
+                Op::Const(v) => stack.push(*v),
+                Op::Neg => {
+                    let v = pop(&mut stack)?;
+                    stack.push(-v);
                 },
-                Token::Multiple => {
-                    let second = stack.pop().unwrap();
-                    let first = stack.pop().unwrap();
-                    stack.push(first * second);
+                Op::Bin(op) => {
+                    let second = pop(&mut stack)?;
+                    let first = pop(&mut stack)?;
+                    stack.push(match op {
+                        BinOp::Add => first + second,
+                        BinOp::Sub => first - second,
+                        BinOp::Mul => first * second,
+                        BinOp::Div => {
+                            if second == 0.0 {
+                                return Err(anyhow!("Zero division"));
+                            }
+                            first / second
+                        },
+                    });
                 },
-                Token::Divide => {
-                    let second = stack.pop().unwrap();
-                    let first = stack.pop().unwrap();
-                    if second == 0.0 {
-                        return Err(anyhow!("Zero division"));
+                Op::Call(f, argc) => {
+                    if *argc > stack.len() {
+                        return Err(anyhow!("stack underflow"));
                     }
-                    stack.push(first / second);
+                    let args = stack.split_off(stack.len() - argc);
+                    stack.push(f.call(&args)?);
                 },
-                _ => return Err(anyhow!("InvalidToken {:?} at pos {}", *t, i))
+            }
+            if let Some(steps) = steps {
+                steps.push(Step {
+                    op: i.to_string(),
+                    stack: stack.clone(),
+                });
             }
         }
-        return Ok(stack.pop().unwrap());
+        rpn::result(stack)
     }
 }
 
-
 #[cfg(test)]
 mod test {
-    use super::{Executable};
+    use anyhow::Result;
+    use crate::lib::functions::{Builtin, Function, Functions};
+    use super::{Executable, Op};
+    use crate::lib::parser::{Parser, Node, BinOp, UnOp};
+    use crate::lib::tokenize::{tokenize, Token, Span, Spanned};
     use crate::lib::scope::Scope;
     use crate::lib::base2::Base2Expr;
     use crate::lib::output;
 
+    fn token(op: BinOp) -> Token {
+        match op {
+            BinOp::Add => Token::Plus,
+            BinOp::Sub => Token::Minus,
+            BinOp::Mul => Token::Multiple,
+            BinOp::Div => Token::Divide,
+        }
+    }
+
+    /// Translates a string in reverse polish notation to the program
+    fn ops(rpn: &str) -> Vec<Op> {
+        tokenize(rpn).unwrap().into_iter().map(|t| match t.token {
+            Token::Plus => Op::Bin(BinOp::Add),
+            Token::Minus => Op::Bin(BinOp::Sub),
+            Token::Multiple => Op::Bin(BinOp::Mul),
+            Token::Divide => Op::Bin(BinOp::Div),
+            Token::Const(v) => Op::Const(v),
+            Token::Ident(name) => Op::Load(name),
+            t => panic!("unexpected token {:?}", t),
+        }).collect()
+    }
+
+    fn try_build(s: &str) -> Result<Executable> {
+        Executable::from_node(&Parser::parse(&tokenize(s)?)?, &Functions::default())
+    }
+
+    fn build(s: &str) -> Executable {
+        try_build(s).unwrap()
+    }
+
+    fn expr(s: &str) -> Result<Base2Expr> {
+        Base2Expr::build(&tokenize(s).unwrap(), &Functions::default())
+    }
+
     #[test]
     fn test_build_executable() {
-        use super::super::tokenize::Token::*;;
         let values = vec![
-            (vec![D, Plus, F],
-             vec![D, F, Plus]),
-            (vec![D, Multiple, F, Divide, Const(25.0)],
-             vec![D, F, Const(25.0), Divide, Multiple]),
-            (vec![Open, D, Plus, E, Close, Multiple, E],
-             vec![D, E, Plus, E, Multiple]),
-            (vec![D, Plus, Open, D, Multiple, E , Divide, Const(10.0), Close],
-             vec![D, D, E, Const(10.0), Divide, Multiple, Plus]),
-            (vec![D, Plus, Open, D, Multiple, Open,  E, Minus, F, Close,  Divide, Const( 25.5), Close],
-             vec![D, D, E, F, Minus, Const(25.5), Divide, Multiple, Plus])
+            ("D + F", "D F +"),
+            ("D * F / 25", "D F * 25 /"),
+            ("D - E - F", "D E - F -"),
+            ("D / E / F", "D E / F /"),
+            ("D + E * F - 1", "D E F * + 1 -"),
+            ("(D + E) * E", "D E + E *"),
+            ("D + (D * E / 10)", "D D E * 10 / +"),
+            ("D + (D * (E - F) / 25.5)", "D D E F - * 25.5 / +"),
+            ("order_total * items_count", "order_total items_count *"),
         ];
 
         for v in values {
-            let out = Executable::build(&v.0).unwrap();
-            assert_eq!(v.1, out.inner)
+            assert_eq!(ops(v.1), build(v.0).inner, "{}", v.0)
         }
     }
 
     #[test]
-    fn test_base2_expr() {
-        use crate::lib::tokenize::Token::*;
-        let v = vec![
-            H, Eq, M, Arrow, K, Eq,
-            D, Plus, Open, D, Multiple, Open,  E, Minus,
-            F, Close,  Divide, Const(25.5), Close];
-        let rs1 = Base2Expr::build(&v).unwrap();
+    fn test_unary() {
+        let load = |s: &str| Op::Load(s.to_string());
+        assert_eq!(vec![load("D"), Op::Neg, Op::Const(5.0), Op::Bin(BinOp::Add)], build("-D + 5").inner);
+        assert_eq!(vec![load("D"), Op::Const(-1.5), Op::Bin(BinOp::Mul)], build("D * -1.5").inner);
+        assert_eq!(vec![load("D"), load("E"), Op::Bin(BinOp::Sub)], build("+D - +E").inner);
 
-        let v = vec![H, Eq, P, Arrow, K, Eq, D];
-        let rs2 = Base2Expr::build(&v).unwrap();
+        let values = vec![
+            ("-D + 5", 3.0),
+            ("D * -1.5", -3.0),
+            ("--D", 2.0),
+            ("-(D - (-E))", -5.0),
+            ("(-(D + E)) * -2", 10.0),
+        ];
+        for v in values {
+            assert_eq!(v.1, build(v.0).run(&Scope::def(2.0, 3, 0)).unwrap(), "{}", v.0);
+        }
+    }
+
+    #[test]
+    fn test_base2_expr() {
+        let rs1 = expr("H = M => K = D + (D * (E - F) / 25.5)").unwrap();
+        let rs2 = expr("H = P => K = D").unwrap();
 
         assert_ne!(rs1, rs2);
         assert_eq!(rs1, rs1);
 
-        assert_eq!(3.0, rs1.run(Scope::def(1.0, 80, 29)).unwrap());
-        assert_eq!(5.0, rs2.run(Scope::def(5.0, 80, 29)).unwrap());
-        assert_eq!(rs2.output, output::H::P);
+        assert_eq!(3.0, rs1.run(&Scope::def(1.0, 80, 29)).unwrap());
+        assert_eq!(5.0, rs2.run(&Scope::def(5.0, 80, 29)).unwrap());
+        assert_eq!(rs2.output, output::H::new("P"));
+
+        let rs3 = expr("H = GOLD => K = D * 0.9").unwrap();
+        assert_eq!(&output::H::new("GOLD"), rs3.output());
+        assert_eq!("H = M => K = D + D * (E - F) / 25.5", rs1.to_string());
+        assert!(expr("H = 1 => K = D").is_err());
+        assert_eq!("expected numeric expression, found condition",
+                   expr("H = M => K = D > 1").unwrap_err().to_string());
+        assert!(expr("H = M => K = D + !E").is_err());
+        assert_eq!("unexpected end of input, expected identifier, number, `(`, `-`, `+` or `!`",
+                   expr("H = M => K = D +").unwrap_err().to_string());
+        assert_eq!("unexpected identifier `E`, expected operator or end of input",
+                   expr("H = M => K = D E").unwrap_err().to_string());
+        assert_eq!("unexpected `=>`, expected category name\nunexpected end of input, expected identifier, number, `(`, `-`, `+` or `!`",
+                   expr("H = => K = D *").unwrap_err().to_string());
+        assert_eq!("unexpected `K`, expected `=>`", expr("H = M K = D").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_functions() {
+        let s = Scope::def(10.0, 3, 12);
+        let values = vec![
+            ("round(D * 1.234, 2)", 12.34),
+            ("max(0, D - F)", 0.0),
+            ("min(D, E, F) * 2", 6.0),
+            ("clamp(D * F, 0, 100)", 100.0),
+            ("pow(E, 2) + sqrt(abs(-16))", 13.0),
+            ("floor(D / 3) + ceil(D / 3)", 7.0),
+            ("-max(-D, round(-E / 2))", 2.0),
+        ];
+        for (e, expected) in values {
+            assert_eq!(expected, build(e).run(&s).unwrap(), "{}", e);
+        }
+
+        assert_eq!("sqrt: domain error, argument -2 is negative",
+                   build("sqrt(E - 5)").run(&s).unwrap_err().to_string());
+        assert_eq!("unknown function tax",
+                   try_build("tax(D)").unwrap_err().to_string());
+        assert_eq!("function round expects 1 to 2 arguments, found 3",
+                   try_build("round(D, 1, 2)").unwrap_err().to_string());
+        assert_eq!("function max expects at least 1 argument, found 0",
+                   try_build("max()").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_verify() {
+        let exec = |rpn: &str| Executable {
+            inner: ops(rpn),
+        };
+        assert!(exec("D E F * +").verify().is_ok());
+        assert_eq!("invalid program: instruction 1 (add) needs 2 values, found 1",
+                   exec("D +").verify().unwrap_err().to_string());
+        assert_eq!("invalid program: leaves 0 values, expected 1", exec("").verify().unwrap_err().to_string());
+        assert_eq!("invalid program: leaves 2 values, expected 1", exec("D E").verify().unwrap_err().to_string());
+        let sqrt = Executable {
+            inner: vec![Op::Const(4.0), Op::Const(9.0), Op::Call(Function::Builtin(Builtin::Sqrt), 2)],
+        };
+        assert_eq!("invalid program: instruction 2 (call sqrt/2) but sqrt expects 1 argument",
+                   sqrt.verify().unwrap_err().to_string());
+
+        // unverified programs fail instead of panicking
+        let s = Scope::def(1.0, 2, 3);
+        assert_eq!("stack underflow", exec("D +").run(&s).unwrap_err().to_string());
+        assert_eq!("stack underflow", exec("").run(&s).unwrap_err().to_string());
+        assert_eq!("1 values left on the stack", exec("D E").run(&s).unwrap_err().to_string());
+        assert!(try_build("").is_err());
+        assert!(try_build("D +").is_err());
+        assert!(try_build("()").is_err());
+    }
+
+    #[test]
+    fn test_named_variables() {
+        let rs = build("order_total - discount * items_count");
+        let s = Scope::default().set("order_total", 100.0).set("discount", 2.5).set("items_count", 4);
+        assert_eq!(90.0, rs.run(&s).unwrap());
+
+        let s = Scope::default().set("order_total", 100.0).set("discount", 2.5);
+        assert_eq!("unknown variable `items_count`", rs.run(&s).unwrap_err().to_string());
+
+        let s = Scope::default().set("order_total", true).set("discount", 2.5).set("items_count", 4);
+        assert_eq!("variable `order_total` is bool, expected number", rs.run(&s).unwrap_err().to_string());
+    }
+
+    /// xorshift, good enough to generate expressions without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    fn generate(rng: &mut Rng, depth: u32) -> Node {
+        if depth > 0 && rng.next(8) == 0 {
+            let op = if rng.next(2) == 0 { UnOp::Neg } else { UnOp::Pos };
+            return Node::Unary(op, Box::new(generate(rng, depth - 1)));
+        }
+        if depth == 0 || rng.next(4) == 0 {
+            return match rng.next(4) {
+                0 => Node::Var("D".to_string()),
+                1 => Node::Var("E".to_string()),
+                2 => Node::Var("F".to_string()),
+                _ => Node::Const((rng.next(100) + 1) as f64 / 4.0),
+            };
+        }
+        let op = match rng.next(4) {
+            0 => BinOp::Add,
+            1 => BinOp::Sub,
+            2 => BinOp::Mul,
+            _ => BinOp::Div,
+        };
+        Node::Binary(op, Box::new(generate(rng, depth - 1)), Box::new(generate(rng, depth - 1)))
+    }
+
+    /// Renders the tree with the minimal set of parentheses (plus some random redundant ones)
+    fn render(node: &Node, rng: &mut Rng, out: &mut Vec<Token>) {
+        match node {
+            Node::Var(name) => out.push(Token::Ident(name.clone())),
+            Node::Const(v) => out.push(Token::Const(*v)),
+            Node::Unary(op, n) => {
+                out.push(if *op == UnOp::Neg { Token::Minus } else { Token::Plus });
+                let wrap = matches!(**n, Node::Binary(..));
+                if wrap {
+                    out.push(Token::Open);
+                }
+                render(n, rng, out);
+                if wrap {
+                    out.push(Token::Close);
+                }
+            },
+            Node::Binary(op, l, r) => {
+                let prec = |n: &Node| match n {
+                    Node::Binary(op, _, _) => op.precedence(),
+                    _ => u8::MAX,
+                };
+                let wrap_l = prec(l) < op.precedence() || rng.next(8) == 0;
+                let wrap_r = prec(r) <= op.precedence() || rng.next(8) == 0;
+                for (wrap, child, last) in [(wrap_l, l, false), (wrap_r, r, true)] {
+                    if wrap {
+                        out.push(Token::Open);
+                    }
+                    render(child, rng, out);
+                    if wrap {
+                        out.push(Token::Close);
+                    }
+                    if !last {
+                        out.push(token(*op));
+                    }
+                }
+            },
+            n => panic!("unexpected node {:?}", n),
+        }
+    }
+
+    /// Straightforward recursive evaluator used as a reference
+    fn eval(node: &Node, s: &Scope) -> Option<f64> {
+        match node {
+            Node::Var(name) => s.number(name).ok(),
+            Node::Const(v) => Some(*v),
+            Node::Unary(UnOp::Neg, n) => Some(-eval(n, s)?),
+            Node::Unary(UnOp::Pos, n) => eval(n, s),
+            Node::Binary(op, l, r) => {
+                let l = eval(l, s)?;
+                let r = eval(r, s)?;
+                match op {
+                    BinOp::Add => Some(l + r),
+                    BinOp::Sub => Some(l - r),
+                    BinOp::Mul => Some(l * r),
+                    BinOp::Div if r == 0.0 => None,
+                    BinOp::Div => Some(l / r),
+                }
+            },
+            n => panic!("unexpected node {:?}", n),
+        }
+    }
+
+    #[test]
+    fn test_reference() {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        let scopes = vec![Scope::def(1.0, 52, 1), Scope::def(-3.5, 0, 7), Scope::def(0.0, 2, 2)];
+        for _ in 0..5000 {
+            let node = generate(&mut rng, 5);
+            let mut tokens = vec![];
+            render(&node, &mut rng, &mut tokens);
+
+            let tokens: Vec<Spanned> = tokens.into_iter().map(|token| Spanned { token, span: Span::default() }).collect();
+            assert_eq!(node, Parser::parse(&tokens).unwrap(), "{:?}", tokens);
+            let exec = Executable::from_node(&Parser::parse(&tokens).unwrap(), &Functions::default()).unwrap();
+            for s in &scopes {
+                assert_eq!(eval(&node, s), exec.run(s).ok(), "{:?}", tokens);
+            }
+        }
     }
-}
\ No newline at end of file
+}
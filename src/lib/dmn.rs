@@ -0,0 +1,674 @@
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use crate::lib::analysis::constant;
+use crate::lib::app::{App, Strategy};
+use crate::lib::expr::Expr;
+use crate::lib::parser::{CmpOp, LogicOp, Node};
+use crate::lib::rule::{RuleSet, RuleSource};
+use crate::lib::tokenize::{is_ident, tokenize_all, Token};
+
+const NAMESPACE: &str = "https://www.omg.org/spec/DMN/20191111/MODEL/";
+
+/// FEEL words the rule syntax has no counterpart for
+const FEEL_KEYWORDS: [&str; 15] = ["if", "then", "else", "for", "some", "every", "in", "satisfies",
+                                   "between", "instance", "function", "and", "or", "not", "null"];
+
+/// Rule set of a DMN document with the element every rule comes from, `rule rule_3` or `decision M`,
+/// and the inputs typed `integer` or `long`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definitions {
+    pub set: RuleSet,
+    pub origins: Vec<String>,
+    pub ints: Vec<String>,
+}
+
+impl Definitions {
+    /// Replaces categories, strategy and rules of `base` keeping its functions and int inputs,
+    /// the integer inputs of the table are declared too. Errors name the element of the rule. Categories of rows missing from the output
+    /// values are declared too. A UNIQUE table fails when rows with different outputs
+    /// can match the same input, rows with the same output may overlap
+    pub fn load(&self, base: &App) -> Result<App> {
+        let mut set = self.set.clone();
+        for r in &self.set.rules {
+            let rule = match base.parse_rule(r) {
+                Ok(rule) => rule,
+                Err(_) => continue,
+            };
+            if let Expr::Base1(e) = rule.expr() {
+                let category = e.output().name().to_string();
+                if !set.categories.contains(&category) {
+                    set.categories.push(category);
+                }
+            }
+        }
+        let base = self.ints.iter().fold(base.clone(), |app, name| app.int_input(name));
+        let app = base.with_rule_set(&set).map_err(|errors| {
+            let all: Vec<String> = errors.iter().map(|e| format!("{}: {}", self.origins[e.rule], e.message)).collect();
+            anyhow!(all.join("\n"))
+        })?;
+        if app.strategy() == Strategy::ErrorOnAmbiguity {
+            if let Some(o) = app.overlaps()?.first() {
+                return Err(anyhow!("hit policy UNIQUE but `{} => H = {}` and `{} => H = {}` can match the same input",
+                                   o.first.condition, o.first.category, o.second.condition, o.second.category));
+            }
+        }
+        Ok(app)
+    }
+}
+
+type Element<'a, 'i> = roxmltree::Node<'a, 'i>;
+
+fn children<'a, 'i: 'a>(n: Element<'a, 'i>, name: &'a str) -> impl Iterator<Item = Element<'a, 'i>> + 'a {
+    n.children().filter(move |c| c.is_element() && c.tag_name().name() == name)
+}
+
+fn child<'a, 'i: 'a>(n: Element<'a, 'i>, name: &'a str) -> Option<Element<'a, 'i>> {
+    children(n, name).next()
+}
+
+/// Content of the `<text>` child
+fn text(n: Element) -> String {
+    child(n, "text").and_then(|t| t.text()).unwrap_or("").trim().to_string()
+}
+
+fn string_literal(s: &str) -> Option<&str> {
+    let s = s.trim();
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        return Some(&s[1..s.len() - 1]);
+    }
+    None
+}
+
+/// Id given by `to_xml`, `rule_7` is 7
+fn number(id: Option<&str>, prefix: &str) -> Option<u64> {
+    id?.strip_prefix(prefix)?.parse().ok()
+}
+
+/// Reads the decision table and the literal expressions of a DMN 1.3 document, earlier
+/// versions are accepted as elements are matched by local name. Table rows become Base1
+/// rules, a decision with a literal expression becomes the formula of the category it is named after
+pub fn parse(xml: &str) -> Result<Definitions> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| anyhow!("invalid DMN document: {}", e))?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "definitions" {
+        return Err(anyhow!("invalid DMN document: the root element is <{}>, expected <definitions>", root.tag_name().name()));
+    }
+    let mut defs = Definitions {
+        set: RuleSet {
+            categories: vec![],
+            strategy: Strategy::ErrorOnAmbiguity,
+            rules: vec![],
+            next_id: 0,
+        },
+        origins: vec![],
+        ints: vec![],
+    };
+    let mut errors = vec![];
+    let mut tables = 0;
+    let mut formulas = vec![];
+    for decision in children(root, "decision") {
+        let name = decision.attribute("name").unwrap_or("");
+        if let Some(table) = child(decision, "decisionTable") {
+            tables += 1;
+            if tables > 1 {
+                return Err(anyhow!("decision {}: only one decision table is supported", name));
+            }
+            if let Err(e) = read_table(table, &mut defs, &mut errors) {
+                errors.push(format!("decision {}: {}", name, e));
+            }
+        } else if let Some(literal) = child(decision, "literalExpression") {
+            let source = literal_expression(&text(literal))
+                .map(|formula| format!("H = {} => K = {}", name, formula));
+            match source {
+                Ok(source) => formulas.push((decision, source)),
+                Err(e) => errors.push(format!("decision {}: {}", name, e)),
+            }
+        } else {
+            let kind = decision.children().find(|c| c.is_element() && !["description", "variable", "question",
+                "allowedAnswers", "informationRequirement", "knowledgeRequirement", "authorityRequirement"]
+                .contains(&c.tag_name().name()));
+            match kind {
+                Some(kind) => errors.push(format!("decision {}: <{}> is not supported, expected a decision table or literal expression",
+                                                  name, kind.tag_name().name())),
+                None => errors.push(format!("decision {}: no decision table or literal expression", name)),
+            }
+        }
+    }
+    for (decision, source) in formulas {
+        defs.origins.push(format!("decision {}", decision.attribute("name").unwrap_or("")));
+        defs.set.rules.push(RuleSource {
+            id: number(decision.attribute("id"), "formula_"),
+            description: child(decision, "description").and_then(|d| d.text()).map(String::from),
+            source,
+            ..RuleSource::default()
+        });
+    }
+    if errors.is_empty() {
+        return Ok(defs);
+    }
+    Err(anyhow!(errors.join("\n")))
+}
+
+/// Input column of a table, `source` is how a test of the column starts
+struct Input {
+    label: String,
+    source: String,
+}
+
+fn read_table(table: Element, defs: &mut Definitions, errors: &mut Vec<String>) -> Result<()> {
+    let policy = table.attribute("hitPolicy").unwrap_or("UNIQUE");
+    if let Some(aggregation) = table.attribute("aggregation") {
+        return Err(anyhow!("hit policy {} with aggregation {} is not supported", policy, aggregation));
+    }
+    defs.set.strategy = match policy {
+        "UNIQUE" | "FIRST" | "PRIORITY" | "COLLECT" => Strategy::from_hit_policy(policy).unwrap_or(Strategy::ErrorOnAmbiguity),
+        _ => return Err(anyhow!("hit policy {} is not supported, expected UNIQUE, FIRST, PRIORITY or COLLECT", policy)),
+    };
+    let mut inputs = vec![];
+    for (i, input) in children(table, "input").enumerate() {
+        let expression = child(input, "inputExpression").map(text).unwrap_or_default();
+        if expression.is_empty() {
+            return Err(anyhow!("input {} has no expression", i + 1));
+        }
+        let int = matches!(child(input, "inputExpression").and_then(|e| e.attribute("typeRef")), Some("integer") | Some("long"));
+        if int && is_ident(&expression) {
+            defs.ints.push(expression.clone());
+        }
+        inputs.push(Input {
+            label: input.attribute("label").map(String::from).unwrap_or_else(|| expression.clone()),
+            source: if is_ident(&expression) { expression } else { format!("({})", expression) },
+        });
+    }
+    let outputs: Vec<Element> = children(table, "output").collect();
+    if outputs.len() != 1 {
+        return Err(anyhow!("only one output is supported, found {}", outputs.len()));
+    }
+    let mut values = vec![];
+    if let Some(v) = child(outputs[0], "outputValues") {
+        for value in text(v).split(',').filter(|s| !s.trim().is_empty()) {
+            let value = string_literal(value).ok_or_else(|| anyhow!("output value `{}` is not a string like \"M\"", value.trim()))?;
+            values.push(value.to_string());
+        }
+    }
+    if defs.set.strategy == Strategy::HighestPriority && values.is_empty() {
+        return Err(anyhow!("hit policy PRIORITY needs the output values in priority order"));
+    }
+    defs.set.categories = values.clone();
+    for (index, rule) in children(table, "rule").enumerate() {
+        let origin = format!("rule {}", rule.attribute("id").map(String::from).unwrap_or_else(|| (index + 1).to_string()));
+        match read_rule(rule, &inputs, &values, defs.set.strategy) {
+            Ok(source) => {
+                defs.set.rules.push(source);
+                defs.origins.push(origin);
+            },
+            Err(e) => errors.push(format!("{}: {}", origin, e)),
+        }
+    }
+    Ok(())
+}
+
+fn read_rule(rule: Element, inputs: &[Input], values: &[String], strategy: Strategy) -> Result<RuleSource> {
+    let entries: Vec<String> = children(rule, "inputEntry").map(text).collect();
+    if entries.len() != inputs.len() {
+        return Err(anyhow!("{} input entries, expected {}", entries.len(), inputs.len()));
+    }
+    let mut tests = vec![];
+    for (entry, input) in entries.iter().zip(inputs) {
+        if let Some(test) = unary_tests(entry, input).map_err(|e| anyhow!("input {}: {}", input.label, e))? {
+            tests.push(test);
+        }
+    }
+    if tests.is_empty() {
+        return Err(anyhow!("no input is tested"));
+    }
+    let outputs: Vec<String> = children(rule, "outputEntry").map(text).collect();
+    if outputs.len() != 1 {
+        return Err(anyhow!("{} output entries, expected 1", outputs.len()));
+    }
+    let category = string_literal(&outputs[0])
+        .ok_or_else(|| anyhow!("output entry `{}` is not a string like \"M\"", outputs[0]))?;
+    let mut priority = 0;
+    if strategy == Strategy::HighestPriority {
+        let pos = values.iter().position(|v| v == category)
+            .ok_or_else(|| anyhow!("output entry \"{}\" is not among the output values", category))?;
+        priority = (values.len() - pos) as i64;
+    }
+    Ok(RuleSource {
+        id: number(rule.attribute("id"), "rule_"),
+        name: rule.attribute("label").map(String::from),
+        description: child(rule, "description").and_then(|d| d.text()).map(String::from),
+        source: format!("{} => H = {}", tests.join(" && "), category),
+        priority,
+        ..RuleSource::default()
+    })
+}
+
+/// Condition of an input entry, `None` for `-`. Supported are `true`, `false`, numbers,
+/// comparisons with a number, ranges like `[1..10)`, lists of those and `not(...)`
+fn unary_tests(entry: &str, input: &Input) -> Result<Option<String>> {
+    let entry = entry.trim();
+    if entry.is_empty() || entry == "-" {
+        return Ok(None);
+    }
+    let list = |tests: &str| tests.split(',').map(|t| unary_test(t.trim(), input)).collect::<Result<Vec<String>>>();
+    if entry.starts_with("not(") && entry.ends_with(')') {
+        let all = list(&entry[4..entry.len() - 1])?;
+        let ne = format!("{} == ", input.source);
+        if let [single] = all.as_slice() {
+            if let Some(c) = single.strip_prefix(&ne) {
+                return Ok(Some(format!("{} != {}", input.source, c)));
+            }
+        }
+        return Ok(Some(format!("!({})", all.join(" || "))));
+    }
+    let all = list(entry)?;
+    if all.len() == 1 {
+        return Ok(all.into_iter().next());
+    }
+    Ok(Some(format!("({})", all.join(" || "))))
+}
+
+fn unary_test(test: &str, input: &Input) -> Result<String> {
+    let unsupported = || anyhow!("unsupported FEEL unary test `{}`, expected -, true, false, a number, \
+                                  a comparison with a number or a range", test);
+    let num = |s: &str| s.trim().parse::<f64>().map_err(|_| unsupported());
+    if test == "true" || test == "false" {
+        if !is_ident(&input.source) {
+            return Err(anyhow!("`{}` needs an input which is a name, not `{}`", test, input.source));
+        }
+        return Ok(if test == "true" { input.source.clone() } else { format!("!{}", input.source) });
+    }
+    for op in &["<=", ">=", "<", ">"] {
+        if let Some(rest) = test.strip_prefix(op) {
+            return Ok(format!("{} {} {}", input.source, op, num(rest)?));
+        }
+    }
+    let open = test.chars().next().unwrap_or(' ');
+    let close = test.chars().last().unwrap_or(' ');
+    if "[(]".contains(open) && "])[".contains(close) && test.len() > 1 {
+        let (low, high) = test[1..test.len() - 1].split_once("..").ok_or_else(unsupported)?;
+        let low_op = if open == '[' { ">=" } else { ">" };
+        let high_op = if close == ']' { "<=" } else { "<" };
+        return Ok(format!("{} {} {} && {} {} {}", input.source, low_op, num(low)?, input.source, high_op, num(high)?));
+    }
+    Ok(format!("{} == {}", input.source, num(test)?))
+}
+
+/// Formula of a literal expression, FEEL beyond arithmetic and function calls is rejected
+fn literal_expression(text: &str) -> Result<String> {
+    if text.contains("**") {
+        return Err(anyhow!("unsupported FEEL construct `**` in `{}`", text));
+    }
+    let (tokens, errors) = tokenize_all(text);
+    for t in &tokens {
+        match &t.token {
+            Token::Ident(name) if FEEL_KEYWORDS.contains(&name.as_str()) => {
+                return Err(anyhow!("unsupported FEEL construct `{}` in `{}`", name, text));
+            },
+            Token::Str(_) => return Err(anyhow!("unsupported FEEL string literal in `{}`", text)),
+            _ => {},
+        }
+    }
+    if let Some(e) = errors.first() {
+        return Err(anyhow!("unsupported FEEL expression `{}`: {}", text, e));
+    }
+    if tokens.is_empty() {
+        return Err(anyhow!("empty literal expression"));
+    }
+    Ok(text.to_string())
+}
+
+/// Test of one input in a row, lists hold the rendered unary tests
+#[derive(Debug, Clone, PartialEq)]
+enum Test {
+    Bool(bool),
+    Cmp(CmpOp, f64),
+    Any(Vec<String>),
+    NotAny(Vec<String>),
+}
+
+/// `input op constant` with the input expression as key
+fn comparison(n: &Node) -> Option<(String, CmpOp, f64)> {
+    match n {
+        Node::Compare(op, l, r) => match (constant(l), constant(r)) {
+            (None, Some(c)) => Some((l.to_string(), *op, c)),
+            (Some(c), None) => Some((r.to_string(), op.flip(), c)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn unary(op: CmpOp, c: f64) -> String {
+    match op {
+        CmpOp::Lt => format!("< {}", c),
+        CmpOp::Le => format!("<= {}", c),
+        CmpOp::Gt => format!("> {}", c),
+        CmpOp::Ge => format!(">= {}", c),
+        CmpOp::Eq => c.to_string(),
+        CmpOp::Ne => format!("not({})", c),
+    }
+}
+
+/// `[a..b]` of a lower and an upper bound in any order
+fn range(x: (CmpOp, f64), y: (CmpOp, f64)) -> Option<String> {
+    let ((lo, a), (hi, b)) = match (x.0, y.0) {
+        (CmpOp::Gt, CmpOp::Lt) | (CmpOp::Gt, CmpOp::Le) | (CmpOp::Ge, CmpOp::Lt) | (CmpOp::Ge, CmpOp::Le) => (x, y),
+        (CmpOp::Lt, CmpOp::Gt) | (CmpOp::Le, CmpOp::Gt) | (CmpOp::Lt, CmpOp::Ge) | (CmpOp::Le, CmpOp::Ge) => (y, x),
+        _ => return None,
+    };
+    Some(format!("{}{}..{}{}", if lo == CmpOp::Ge { "[" } else { "(" }, a, b, if hi == CmpOp::Le { "]" } else { ")" }))
+}
+
+/// Unary tests of a `||` of comparisons and ranges of the same input
+fn items(n: &Node, key: &mut Option<String>, out: &mut Vec<String>) -> Option<()> {
+    let mut same = |k: String| match key {
+        Some(key) => *key == k,
+        None => {
+            *key = Some(k);
+            true
+        },
+    };
+    match n {
+        Node::Logic(LogicOp::Or, l, r) => {
+            items(l, key, out)?;
+            items(r, key, out)?;
+        },
+        Node::Logic(LogicOp::And, l, r) => {
+            let ((k, a, x), (k2, b, y)) = (comparison(l)?, comparison(r)?);
+            if k != k2 || !same(k) {
+                return None;
+            }
+            out.push(range((a, x), (b, y))?);
+        },
+        _ => {
+            let (k, op, c) = comparison(n)?;
+            if !same(k) {
+                return None;
+            }
+            out.push(unary(op, c));
+        },
+    }
+    Some(())
+}
+
+/// Tests of a condition made of `&&` over inputs, negated inputs, comparisons with a constant
+/// and `||` lists of those comparisons, keyed by the input expression
+fn tests(n: &Node, out: &mut Vec<(String, Test)>) -> Option<()> {
+    let list = |n: &Node| {
+        let (mut key, mut all) = (None, vec![]);
+        items(n, &mut key, &mut all)?;
+        Some((key?, all))
+    };
+    match n {
+        Node::Var(name) => out.push((name.clone(), Test::Bool(true))),
+        Node::Not(x) => match &**x {
+            Node::Var(name) => out.push((name.clone(), Test::Bool(false))),
+            x => {
+                let (key, all) = list(x)?;
+                out.push((key, Test::NotAny(all)));
+            },
+        },
+        Node::Logic(LogicOp::And, l, r) => {
+            tests(l, out)?;
+            tests(r, out)?;
+        },
+        Node::Logic(LogicOp::Or, _, _) => {
+            let (key, all) = list(n)?;
+            out.push((key, Test::Any(all)));
+        },
+        _ => {
+            let (key, op, c) = comparison(n)?;
+            out.push((key, Test::Cmp(op, c)));
+        },
+    }
+    Some(())
+}
+
+/// Input entry holding all tests of one input
+fn entry(all: &[Test]) -> Option<String> {
+    match all {
+        [] => Some("-".to_string()),
+        [Test::Bool(v)] => Some(v.to_string()),
+        [Test::Cmp(op, c)] => Some(unary(*op, *c)),
+        [Test::Cmp(a, x), Test::Cmp(b, y)] => range((*a, *x), (*b, *y)),
+        [Test::Any(items)] => Some(items.join(", ")),
+        [Test::NotAny(items)] => Some(format!("not({})", items.join(", "))),
+        _ => None,
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// DMN 1.3 document of the `App`: Base1 rules are the rows of a decision table deciding `H`,
+/// each Base2 rule a decision named after its category with the formula as literal expression.
+/// FEEL type of an input column, declared int inputs are `integer`
+fn type_ref(app: &App, key: &str, boolean: bool) -> &'static str {
+    if boolean {
+        "boolean"
+    } else if app.int_inputs().iter().any(|i| i == key) {
+        "integer"
+    } else {
+        "number"
+    }
+}
+
+/// Fails on conditions a row can not hold, like `||` or two tests of an input which are no range
+pub fn to_xml(app: &App) -> Result<String> {
+    let mut inputs: Vec<(String, bool)> = vec![];
+    let mut rows = vec![];
+    for r in app.rules() {
+        let e = match r.expr() {
+            Expr::Base1(e) => e,
+            Expr::Base2(_) => continue,
+        };
+        let mut found = vec![];
+        if tests(e.condition(), &mut found).is_none() {
+            return Err(anyhow!("rule {} `{}` is not a conjunction of tests of single inputs", r.id(), r.source()));
+        }
+        let mut by_input: Vec<(String, Vec<Test>)> = vec![];
+        for (key, test) in found {
+            let boolean = matches!(test, Test::Bool(_));
+            match inputs.iter().find(|(k, _)| *k == key) {
+                Some((_, b)) if *b != boolean => {
+                    return Err(anyhow!("rule {} `{}` tests {} as {}, other rules as {}", r.id(), r.source(), key,
+                                       if boolean { "boolean" } else { "number" }, if boolean { "number" } else { "boolean" }));
+                },
+                Some(_) => {},
+                None => inputs.push((key.clone(), boolean)),
+            }
+            match by_input.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, all)) => if !all.contains(&test) { all.push(test) },
+                None => by_input.push((key, vec![test])),
+            }
+        }
+        rows.push((r, e.output().name().to_string(), by_input));
+    }
+
+    let mut values: Vec<String> = app.categories().iter().map(|h| h.name().to_string()).collect();
+    if app.strategy() == Strategy::HighestPriority {
+        let mut priorities: HashMap<&str, i64> = HashMap::new();
+        for (r, category, _) in &rows {
+            match priorities.insert(category, r.priority()) {
+                Some(p) if p != r.priority() => {
+                    return Err(anyhow!("rules for {} have different priorities, DMN PRIORITY orders outputs", category));
+                },
+                _ => {},
+            }
+        }
+        values.sort_by_key(|v| Reverse(priorities.get(v.as_str()).cloned()));
+    }
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!("<definitions xmlns=\"{}\" id=\"sprout\" name=\"sprout\" namespace=\"urn:sprout\">\n", NAMESPACE));
+    xml.push_str(&format!("  <decision id=\"H\" name=\"H\">\n    <decisionTable id=\"table\" hitPolicy=\"{}\">\n",
+                          app.strategy().hit_policy()));
+    for (i, (key, boolean)) in inputs.iter().enumerate() {
+        xml.push_str(&format!("      <input id=\"input_{}\" label=\"{}\">\n", i + 1, escape(key)));
+        xml.push_str(&format!("        <inputExpression typeRef=\"{}\">\n          <text>{}</text>\n        </inputExpression>\n",
+                              type_ref(app, key, *boolean), escape(key)));
+        xml.push_str("      </input>\n");
+    }
+    let quoted: Vec<String> = values.iter().map(|v| format!("\"{}\"", v)).collect();
+    xml.push_str(&format!("      <output id=\"output\" name=\"H\" typeRef=\"string\">\n        <outputValues>\n          <text>{}</text>\n\
+                           \x20       </outputValues>\n      </output>\n", escape(&quoted.join(","))));
+    for (r, category, by_input) in rows {
+        xml.push_str(&format!("      <rule id=\"rule_{}\"", r.id()));
+        if let Some(name) = r.name() {
+            xml.push_str(&format!(" label=\"{}\"", escape(name)));
+        }
+        xml.push_str(">\n");
+        if let Some(description) = r.description() {
+            xml.push_str(&format!("        <description>{}</description>\n", escape(description)));
+        }
+        for (key, _) in &inputs {
+            let all = by_input.iter().find(|(k, _)| k == key).map(|(_, all)| all.as_slice()).unwrap_or(&[]);
+            let text = entry(all).ok_or_else(|| anyhow!("rule {} `{}` tests {} in a way one entry can not hold", r.id(), r.source(), key))?;
+            xml.push_str(&format!("        <inputEntry>\n          <text>{}</text>\n        </inputEntry>\n", escape(&text)));
+        }
+        xml.push_str(&format!("        <outputEntry>\n          <text>{}</text>\n        </outputEntry>\n      </rule>\n",
+                              escape(&format!("\"{}\"", category))));
+    }
+    xml.push_str("    </decisionTable>\n  </decision>\n");
+    for r in app.rules() {
+        if let Expr::Base2(e) = r.expr() {
+            xml.push_str(&format!("  <decision id=\"formula_{}\" name=\"{}\">\n", r.id(), escape(e.output().name())));
+            if let Some(description) = r.description() {
+                xml.push_str(&format!("    <description>{}</description>\n", escape(description)));
+            }
+            xml.push_str(&format!("    <literalExpression>\n      <text>{}</text>\n    </literalExpression>\n  </decision>\n",
+                                  escape(&e.formula().to_string())));
+        }
+    }
+    xml.push_str("</definitions>\n");
+    Ok(xml)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::app::{App, Strategy};
+    use crate::lib::scope::Scope;
+    use super::parse;
+
+    const DMN: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<definitions xmlns="https://www.omg.org/spec/DMN/20191111/MODEL/" id="pricing" name="pricing" namespace="urn:pricing">
+  <decision id="category" name="category">
+    <decisionTable hitPolicy="PRIORITY">
+      <input label="VIP"><inputExpression typeRef="boolean"><text>is_vip</text></inputExpression></input>
+      <input label="Total"><inputExpression typeRef="number"><text>D</text></inputExpression></input>
+      <input label="Items"><inputExpression typeRef="number"><text>E * 2</text></inputExpression></input>
+      <output name="H" typeRef="string"><outputValues><text>"GOLD","M","P"</text></outputValues></output>
+      <rule id="vip">
+        <description>vip customers</description>
+        <inputEntry><text>true</text></inputEntry>
+        <inputEntry><text>&gt;= 100</text></inputEntry>
+        <inputEntry><text>-</text></inputEntry>
+        <outputEntry><text>"GOLD"</text></outputEntry>
+      </rule>
+      <rule id="rule_9">
+        <inputEntry><text>-</text></inputEntry>
+        <inputEntry><text>[10..100)</text></inputEntry>
+        <inputEntry><text>not(1, 2)</text></inputEntry>
+        <outputEntry><text>"M"</text></outputEntry>
+      </rule>
+      <rule>
+        <inputEntry><text>false</text></inputEntry>
+        <inputEntry><text>&lt; 10, 500</text></inputEntry>
+        <inputEntry><text>-</text></inputEntry>
+        <outputEntry><text>"P"</text></outputEntry>
+      </rule>
+    </decisionTable>
+  </decision>
+  <decision id="gold" name="GOLD"><literalExpression><text>D * 0.8</text></literalExpression></decision>
+  <decision name="M"><literalExpression><text>D - 5</text></literalExpression></decision>
+  <decision name="P"><literalExpression><text>max(D - 1, 0)</text></literalExpression></decision>
+</definitions>"#;
+
+    #[test]
+    fn test_parse() {
+        let defs = parse(DMN).unwrap();
+        assert_eq!(vec!["GOLD", "M", "P"], defs.set.categories);
+        assert_eq!(Strategy::HighestPriority, defs.set.strategy);
+        let rules: Vec<(Option<u64>, &str, i64)> = defs.set.rules.iter().map(|r| (r.id, r.source.as_str(), r.priority)).collect();
+        assert_eq!(vec![
+            (None, "is_vip && D >= 100 => H = GOLD", 3),
+            (Some(9), "D >= 10 && D < 100 && !((E * 2) == 1 || (E * 2) == 2) => H = M", 2),
+            (None, "!is_vip && (D < 10 || D == 500) => H = P", 1),
+            (None, "H = GOLD => K = D * 0.8", 0),
+            (None, "H = M => K = D - 5", 0),
+            (None, "H = P => K = max(D - 1, 0)", 0),
+        ], rules);
+        assert_eq!(Some("vip customers"), defs.set.rules[0].description.as_deref());
+        assert_eq!(vec!["rule vip", "rule rule_9", "rule 3", "decision GOLD", "decision M", "decision P"], defs.origins);
+
+        let app = App::new().import_dmn(DMN).unwrap();
+        let scope: Scope = serde_json::from_str(r#"{"is_vip": true, "D": 150, "E": 1}"#).unwrap();
+        assert_eq!(120.0, app.run(&scope).unwrap());
+
+        // rows declare their categories without output values
+        let unique = DMN.replace("PRIORITY", "UNIQUE").replace(r#"<outputValues><text>"GOLD","M","P"</text></outputValues>"#, "");
+        let app = App::new().import_dmn(&unique).unwrap();
+        assert_eq!(vec!["GOLD", "M", "P"], app.rule_set().categories);
+        assert_eq!(120.0, app.run(&scope).unwrap());
+        assert_eq!("hit policy UNIQUE but `D >= 100 => H = GOLD` and `!is_vip && (D < 10 || D == 500) => H = P` \
+                    can match the same input",
+                   App::new().import_dmn(&unique.replace("<text>true</text>", "<text>-</text>")).unwrap_err().to_string());
+        // rows only overlapping if `E * 2` takes some value are not rejected, the analysis does not solve it
+        assert!(App::new().import_dmn(&unique.replace("&gt;= 100", "&gt;= 50")).is_ok());
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |s: &str, from: &str, to: &str| parse(&s.replace(from, to)).unwrap_err().to_string();
+        assert_eq!("decision category: hit policy ANY is not supported, expected UNIQUE, FIRST, PRIORITY or COLLECT",
+                   err(DMN, "PRIORITY", "ANY"));
+        assert_eq!("decision category: hit policy COLLECT with aggregation SUM is not supported",
+                   err(DMN, "hitPolicy=\"PRIORITY\"", "hitPolicy=\"COLLECT\" aggregation=\"SUM\""));
+        assert_eq!("rule vip: input Total: unsupported FEEL unary test `\"high\"`, expected -, true, false, a number, \
+                    a comparison with a number or a range", err(DMN, "&gt;= 100", "\"high\""));
+        assert_eq!("rule vip: input Items: `true` needs an input which is a name, not `(E * 2)`",
+                   err(DMN, "<inputEntry><text>-</text></inputEntry>\n        <outputEntry><text>\"GOLD\"", "<inputEntry><text>true</text></inputEntry>\n        <outputEntry><text>\"GOLD\""));
+        assert_eq!("decision GOLD: unsupported FEEL construct `if` in `if D > 10 then D else 0`",
+                   err(DMN, "D * 0.8", "if D &gt; 10 then D else 0"));
+        assert_eq!("decision M: unsupported FEEL construct `**` in `D ** 2`", err(DMN, "D - 5", "D ** 2"));
+        assert_eq!("decision P: <context> is not supported, expected a decision table or literal expression",
+                   err(DMN, "<literalExpression><text>max(D - 1, 0)</text></literalExpression>", "<context/>"));
+        assert_eq!("invalid DMN document: the root element is <model>, expected <definitions>",
+                   err(DMN, "definitions", "model"));
+        assert!(err(DMN, "</rule>", "").starts_with("invalid DMN document"));
+
+        let app = App::new().import_dmn(&DMN.replace("D - 5", "D -"));
+        assert_eq!("decision M: unexpected end of input, expected identifier, number, `(`, `-`, `+` or `!`",
+                   app.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let app = App::new().int_input("D").import_dmn(DMN).unwrap()
+            .add_with_priority("!is_vip && D > 1 && D <= 5 => H = P", 1).unwrap();
+        let xml = app.export_dmn().unwrap();
+        assert!(xml.contains("<text>(1..5]</text>"), "{}", xml);
+        assert!(xml.contains("<inputExpression typeRef=\"integer\">\n          <text>D</text>"), "{}", xml);
+        assert!(xml.contains("<text>&quot;GOLD&quot;,&quot;M&quot;,&quot;P&quot;</text>"), "{}", xml);
+        let back = App::new().import_dmn(&xml).unwrap();
+        let rules = |a: &App| {
+            let mut all: Vec<_> = a.rules().iter().map(|r| (r.id(), r.expr().clone(), r.priority())).collect();
+            all.sort_by_key(|r| r.0);
+            all
+        };
+        assert_eq!(rules(&app), rules(&back));
+        assert_eq!(["D"], back.int_inputs());
+        assert_eq!(xml, back.export_dmn().unwrap());
+
+        let default = App::new().import_dmn(&App::default().export_dmn().unwrap()).unwrap();
+        assert_eq!(Strategy::FirstMatch, default.strategy());
+        assert_eq!(App::default().rules().len(), default.rules().len());
+
+        assert_eq!("rule 7 `A || B => H = M` is not a conjunction of tests of single inputs",
+                   App::default().add("A || B => H = M").unwrap().export_dmn().unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::lib::app::{App, Strategy};
+use crate::lib::error::RuleError;
+use crate::lib::expr::Expr;
+use crate::lib::parser::{BinOp, CmpOp, LogicOp, Node, UnOp};
+use crate::lib::rule::{first_match, RuleSet, RuleSource};
+use crate::lib::tokenize::{tokenize, Token};
+
+/// Text format of a `Document`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// Format by its name or file extension: `json`, `yaml`, `yml` or `toml`
+    pub fn from_name(name: &str) -> Result<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            _ => Err(anyhow!("unknown format `{}`, expected json, yaml or toml", name)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+        })
+    }
+}
+
+/// Rule set split into the conditions picking a category and the formulas computing K,
+/// the form rule sets are exchanged with other tools in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    pub categories: Vec<String>,
+    #[serde(default = "first_match")]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub next_id: u64,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub formulas: Vec<Formula>,
+}
+
+/// Base1 rule, `<condition> => H = <category>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub priority: i64,
+    pub category: String,
+    pub condition: Expression,
+}
+
+/// Base2 rule, `H = <category> => K = <formula>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Formula {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub priority: i64,
+    pub category: String,
+    pub formula: Expression,
+}
+
+/// Condition or formula as source text or as syntax tree
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Expression {
+    Source(String),
+    Tree(Ast),
+}
+
+/// Syntax tree of a condition or formula, `A && !B` is `{"and": [{"var": "A"}, {"not": {"var": "B"}}]}`.
+/// Operators taking a list apply left to right, comparisons take two operands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Ast {
+    Var(String),
+    Const(f64),
+    Neg(Box<Ast>),
+    Pos(Box<Ast>),
+    Not(Box<Ast>),
+    And(Vec<Ast>),
+    Or(Vec<Ast>),
+    Add(Vec<Ast>),
+    Sub(Vec<Ast>),
+    Mul(Vec<Ast>),
+    Div(Vec<Ast>),
+    Lt(Vec<Ast>),
+    Le(Vec<Ast>),
+    Gt(Vec<Ast>),
+    Ge(Vec<Ast>),
+    Eq(Vec<Ast>),
+    Ne(Vec<Ast>),
+    Call { name: String, args: Vec<Ast> },
+}
+
+impl From<&Node> for Ast {
+    fn from(n: &Node) -> Ast {
+        let boxed = |n: &Node| Box::new(Ast::from(n));
+        let chain = |n: &Node| n.chain().into_iter().map(Ast::from).collect();
+        match n {
+            Node::Var(name) => Ast::Var(name.clone()),
+            Node::Const(v) => Ast::Const(*v),
+            Node::Unary(UnOp::Neg, x) => Ast::Neg(boxed(x)),
+            Node::Unary(UnOp::Pos, x) => Ast::Pos(boxed(x)),
+            Node::Not(x) => Ast::Not(boxed(x)),
+            Node::Logic(op, _, _) => match op {
+                LogicOp::And => Ast::And(chain(n)),
+                LogicOp::Or => Ast::Or(chain(n)),
+            },
+            Node::Binary(op, _, _) => match op {
+                BinOp::Add => Ast::Add(chain(n)),
+                BinOp::Sub => Ast::Sub(chain(n)),
+                BinOp::Mul => Ast::Mul(chain(n)),
+                BinOp::Div => Ast::Div(chain(n)),
+            },
+            Node::Compare(op, l, r) => {
+                let operands = vec![Ast::from(&**l), Ast::from(&**r)];
+                match op {
+                    CmpOp::Lt => Ast::Lt(operands),
+                    CmpOp::Le => Ast::Le(operands),
+                    CmpOp::Gt => Ast::Gt(operands),
+                    CmpOp::Ge => Ast::Ge(operands),
+                    CmpOp::Eq => Ast::Eq(operands),
+                    CmpOp::Ne => Ast::Ne(operands),
+                }
+            },
+            Node::Call(name, args) => Ast::Call {
+                name: name.clone(),
+                args: args.iter().map(Ast::from).collect(),
+            },
+        }
+    }
+}
+
+impl Ast {
+    /// Tree in the shape the parser builds, a negative constant is a negated one
+    pub fn to_node(&self) -> Result<Node> {
+        let boxed = |a: &Ast| -> Result<Box<Node>> { Ok(Box::new(a.to_node()?)) };
+        Ok(match self {
+            Ast::Var(name) => Node::Var(name.clone()),
+            Ast::Const(v) if !v.is_finite() => return Err(anyhow!("`const` must be a finite number")),
+            Ast::Const(v) if *v < 0.0 => Node::Unary(UnOp::Neg, Box::new(Node::Const(-v))),
+            Ast::Const(v) => Node::Const(*v),
+            Ast::Neg(a) => Node::Unary(UnOp::Neg, boxed(a)?),
+            Ast::Pos(a) => Node::Unary(UnOp::Pos, boxed(a)?),
+            Ast::Not(a) => Node::Not(boxed(a)?),
+            Ast::And(all) => fold("and", all, |l, r| Node::Logic(LogicOp::And, l, r))?,
+            Ast::Or(all) => fold("or", all, |l, r| Node::Logic(LogicOp::Or, l, r))?,
+            Ast::Add(all) => fold("add", all, |l, r| Node::Binary(BinOp::Add, l, r))?,
+            Ast::Sub(all) => fold("sub", all, |l, r| Node::Binary(BinOp::Sub, l, r))?,
+            Ast::Mul(all) => fold("mul", all, |l, r| Node::Binary(BinOp::Mul, l, r))?,
+            Ast::Div(all) => fold("div", all, |l, r| Node::Binary(BinOp::Div, l, r))?,
+            Ast::Lt(all) => compare("lt", all, CmpOp::Lt)?,
+            Ast::Le(all) => compare("le", all, CmpOp::Le)?,
+            Ast::Gt(all) => compare("gt", all, CmpOp::Gt)?,
+            Ast::Ge(all) => compare("ge", all, CmpOp::Ge)?,
+            Ast::Eq(all) => compare("eq", all, CmpOp::Eq)?,
+            Ast::Ne(all) => compare("ne", all, CmpOp::Ne)?,
+            Ast::Call { name, args } => Node::Call(name.clone(), args.iter().map(Ast::to_node).collect::<Result<_>>()?),
+        })
+    }
+}
+
+fn fold<F>(name: &str, operands: &[Ast], f: F) -> Result<Node> where F: Fn(Box<Node>, Box<Node>) -> Node {
+    if operands.len() < 2 {
+        return Err(anyhow!("`{}` needs at least two operands, got {}", name, operands.len()));
+    }
+    let first = operands[0].to_node()?;
+    operands[1..].iter().try_fold(first, |l, r| Ok(f(Box::new(l), Box::new(r.to_node()?))))
+}
+
+fn compare(name: &str, operands: &[Ast], op: CmpOp) -> Result<Node> {
+    if operands.len() != 2 {
+        return Err(anyhow!("`{}` needs two operands, got {}", name, operands.len()));
+    }
+    Ok(Node::Compare(op, Box::new(operands[0].to_node()?), Box::new(operands[1].to_node()?)))
+}
+
+impl Expression {
+    /// Text of the expression, trees are printed with the minimal set of parentheses
+    pub fn to_source(&self) -> Result<String> {
+        match self {
+            Expression::Source(s) => Ok(s.trim().to_string()),
+            Expression::Tree(t) => Ok(t.to_node()?.to_string()),
+        }
+    }
+}
+
+impl Condition {
+    fn to_rule(&self) -> Result<RuleSource> {
+        Ok(RuleSource {
+            id: self.id,
+            name: self.name.clone(),
+            owner: self.owner.clone(),
+            description: self.description.clone(),
+            source: join(&self.condition.to_source()?, &format!("H = {}", self.category)),
+            priority: self.priority,
+        })
+    }
+}
+
+impl Formula {
+    fn to_rule(&self) -> Result<RuleSource> {
+        Ok(RuleSource {
+            id: self.id,
+            name: self.name.clone(),
+            owner: self.owner.clone(),
+            description: self.description.clone(),
+            source: join(&format!("H = {}", self.category), &format!("K = {}", self.formula.to_source()?)),
+            priority: self.priority,
+        })
+    }
+}
+
+/// Rule text of both sides, a comment ending the left side must not swallow `=>`
+fn join(left: &str, right: &str) -> String {
+    let separator = if left.contains('#') { "\n" } else { " " };
+    format!("{}{}=> {}", left, separator, right)
+}
+
+/// Condition before `=>` and formula after `K =` as written in the rule, comments included
+fn sides(source: &str) -> (&str, &str) {
+    let tokens = tokenize(source).unwrap_or_default();
+    match tokens.iter().position(|t| t.token == Token::Arrow) {
+        Some(arrow) => {
+            let formula = tokens.get(arrow + 2).map(|t| t.span.end).unwrap_or_else(|| source.len());
+            (source[..tokens[arrow].span.start].trim(), source[formula..].trim())
+        },
+        None => (source, ""),
+    }
+}
+
+impl Document {
+    /// Categories, strategy and rules of the `App` with their ids and metadata. Conditions and
+    /// formulas keep the text they were written with, with `trees` they are syntax trees instead
+    pub fn from_app(app: &App, trees: bool) -> Document {
+        let set = app.rule_set();
+        let mut doc = Document {
+            categories: set.categories,
+            strategy: set.strategy,
+            next_id: set.next_id,
+            conditions: vec![],
+            formulas: vec![],
+        };
+        for r in app.rules() {
+            let (condition, formula) = sides(r.source());
+            match r.expr() {
+                Expr::Base1(e) => doc.conditions.push(Condition {
+                    id: Some(r.id()),
+                    name: r.name().map(String::from),
+                    owner: r.owner().map(String::from),
+                    description: r.description().map(String::from),
+                    priority: r.priority(),
+                    category: e.output().name().to_string(),
+                    condition: if trees { Expression::Tree(Ast::from(e.condition())) } else { Expression::Source(condition.to_string()) },
+                }),
+                Expr::Base2(e) => doc.formulas.push(Formula {
+                    id: Some(r.id()),
+                    name: r.name().map(String::from),
+                    owner: r.owner().map(String::from),
+                    description: r.description().map(String::from),
+                    priority: r.priority(),
+                    category: e.output().name().to_string(),
+                    formula: if trees { Expression::Tree(Ast::from(e.formula())) } else { Expression::Source(formula.to_string()) },
+                }),
+            }
+        }
+        doc
+    }
+
+    pub fn from_text(text: &str, format: Format) -> Result<Document> {
+        let doc: std::result::Result<Document, String> = match format {
+            Format::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+        };
+        doc.map_err(|e| anyhow!("invalid {} document: {}", format, e))
+    }
+
+    pub fn to_text(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Json => serde_json::to_string_pretty(self)?,
+            Format::Yaml => serde_yaml::to_string(self)?,
+            // the toml serializer can not write the variants of `Ast` itself,
+            // its `Value` also puts plain values before tables as toml requires
+            Format::Toml => toml::to_string(&toml::Value::try_from(serde_json::to_value(self)?)?)?,
+        })
+    }
+
+    /// Rebuilds the rules with the functions of `base` like `App::with_rule_set`, conditions
+    /// first. Errors point at `conditions[i]` or `formulas[i]`
+    pub fn load(&self, base: &App) -> Result<App> {
+        let rules: Vec<(String, Result<RuleSource>)> = self.conditions.iter().enumerate()
+            .map(|(i, c)| (format!("conditions[{}]:", i), c.to_rule()))
+            .chain(self.formulas.iter().enumerate().map(|(i, f)| (format!("formulas[{}]:", i), f.to_rule())))
+            .collect();
+        let mut set = RuleSet {
+            categories: self.categories.clone(),
+            strategy: self.strategy,
+            rules: vec![],
+            next_id: self.next_id,
+        };
+        let mut errors = vec![];
+        for (index, (_, r)) in rules.iter().enumerate() {
+            match r {
+                Ok(r) => set.rules.push(r.clone()),
+                Err(e) => errors.push(RuleError { rule: index, message: e.to_string(), syntax: None }),
+            }
+        }
+        if errors.is_empty() {
+            errors = match base.with_rule_set(&set) {
+                Ok(app) => return Ok(app),
+                Err(errors) => errors,
+            };
+        }
+        let all: Vec<String> = errors.iter().map(|e| {
+            let (origin, r) = &rules[e.rule];
+            let source = r.as_ref().map(|r| r.source.as_str()).unwrap_or("");
+            e.render_at(source, origin)
+        }).collect();
+        Err(anyhow!(all.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::app::App;
+    use crate::lib::rule::RuleSource;
+    use crate::lib::scope::Scope;
+    use super::{Ast, Document, Expression, Format};
+
+    const YAML: &str = "categories: [GOLD, M]
+conditions:
+  - category: GOLD
+    priority: 5
+    condition:
+      and:
+        - var: is_vip
+        - gt: [{var: D}, {const: 100}]
+  - category: M
+    condition: A && !B
+formulas:
+  - category: GOLD
+    formula:
+      sub: [{var: D}, {mul: [{var: D}, {const: 0.2}]}, {const: 1}]
+  - category: M
+    formula: D - 5
+";
+
+    fn app() -> App {
+        let app = App::default()
+            .category("GOLD")
+            .add_with_priority("is_vip\n  && D > 100 # big orders\n  => H = GOLD", 3).unwrap()
+            .add("!A && (B || -D < max(E, 2)) => H = T").unwrap()
+            .add("H = GOLD => K = D - (D - 1) * 2 / 3 # discount").unwrap();
+        let described = |source: &str| RuleSource {
+            id: None,
+            name: Some("vip".to_string()),
+            owner: Some("sales".to_string()),
+            description: Some("orders of vip customers".to_string()),
+            source: source.to_string(),
+            priority: 0,
+        };
+        let app = app.put_rule(1, app.parse_rule(&described("A && B && !C => H = M")).unwrap()).unwrap();
+        app.put_rule(4, app.parse_rule(&described("H = M => K = D + (D * E / 10)")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let app = app();
+        for format in &[Format::Json, Format::Yaml, Format::Toml] {
+            for trees in &[false, true] {
+                let text = app.export(*format, *trees).unwrap();
+                let back = app.import(&text, *format).unwrap();
+                // conditions come before formulas in the document
+                let exprs = |a: &App| {
+                    let mut all: Vec<_> = a.rules().iter().map(|r| {
+                        let meta = (r.name().map(String::from), r.owner().map(String::from), r.description().map(String::from));
+                        (r.id(), r.expr().category().to_string(), r.expr().clone(), r.priority(), meta)
+                    }).collect();
+                    all.sort_by_key(|r| r.0);
+                    all
+                };
+                assert_eq!(exprs(&app), exprs(&back), "{} {}", format, trees);
+                assert_eq!(Some("sales"), back.rule(4).and_then(|r| r.owner()));
+                assert_eq!(app.rule_set().next_id, back.rule_set().next_id);
+                assert_eq!(text, back.export(*format, *trees).unwrap());
+            }
+        }
+        assert_eq!(App::default(), App::default().import(&App::default().export(Format::Yaml, false).unwrap(), Format::Yaml).unwrap());
+    }
+
+    #[test]
+    fn test_sources() {
+        let doc = Document::from_app(&app(), false);
+        assert_eq!(Expression::Source("is_vip\n  && D > 100 # big orders".into()), doc.conditions[3].condition);
+        assert_eq!(Expression::Source("D - (D - 1) * 2 / 3 # discount".into()), doc.formulas[3].formula);
+        assert_eq!(3, doc.conditions[3].priority);
+
+        let doc = Document::from_app(&app(), true);
+        assert_eq!(Expression::Tree(Ast::And(vec![
+            Ast::Var("A".into()),
+            Ast::Var("B".into()),
+            Ast::Not(Box::new(Ast::Var("C".into()))),
+        ])), doc.conditions[0].condition);
+    }
+
+    #[test]
+    fn test_import() {
+        let app = App::new().import(YAML, Format::Yaml).unwrap();
+        let sources: Vec<&str> = app.rules().iter().map(|r| r.source()).collect();
+        assert_eq!(vec![
+            "is_vip && D > 100 => H = GOLD",
+            "A && !B => H = M",
+            "H = GOLD => K = D - D * 0.2 - 1",
+            "H = M => K = D - 5",
+        ], sources);
+        assert_eq!(5, app.rules()[0].priority());
+        let scope: Scope = serde_json::from_str(r#"{"is_vip": true, "A": false, "B": false, "D": 200}"#).unwrap();
+        assert_eq!(159.0, app.run(&scope).unwrap());
+
+        let err = |s: &str| App::new().import(s, Format::Yaml).unwrap_err().to_string();
+        assert_eq!("error: `gt` needs two operands, got 1\n --> conditions[0]",
+                   err("categories: [M]\nconditions:\n  - {category: M, condition: {gt: [{var: D}]}}"));
+        assert!(err("categories: [M]\nconditions:\n  - {category: M, condition: A}\n  - {category: M, condition: A && }")
+            .starts_with("error: unexpected `=>`, expected identifier, number, `(`, `-`, `+` or `!`\n --> conditions[1]:1:6"));
+        assert_eq!("error: category T is not declared\n --> formulas[0]",
+                   err("categories: [M]\nformulas:\n  - {category: T, formula: D}"));
+        assert!(err("categories: M").starts_with("invalid yaml document"));
+        assert!(Format::from_name("xml").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use crate::lib::tokenize::Span;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    InvalidCharacter,
+    InvalidNumber,
+    UnterminatedString,
+    UnexpectedToken,
+    UnexpectedEnd,
+    ChainedComparison,
+    ExpectedCondition,
+    ExpectedNumber,
+}
+
+/// Error in the source of a rule, `expected` and `found` describe tokens the way
+/// they are written in messages
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyntaxError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    pub expected: Vec<String>,
+    pub found: Option<String>,
+}
+
+impl SyntaxError {
+    pub fn new(kind: ErrorKind, span: Span) -> SyntaxError {
+        SyntaxError {
+            kind,
+            span,
+            expected: vec![],
+            found: None,
+        }
+    }
+
+    pub fn expected(mut self, expected: &[&str]) -> SyntaxError {
+        self.expected = expected.iter().map(|e| e.to_string()).collect();
+        self
+    }
+
+    pub fn found<S: Into<String>>(mut self, found: S) -> SyntaxError {
+        self.found = Some(found.into());
+        self
+    }
+
+    /// Line and column of the span start, both counted from 1
+    pub fn position(&self, source: &str) -> (usize, usize) {
+        position(source, self.span.start)
+    }
+
+    /// Message followed by the source line with a caret under the span
+    pub fn render(&self, source: &str) -> String {
+        self.render_at(source, "")
+    }
+
+    /// Same as `render` with `origin` (e.g. `exprs[3]:`) put before the line and column
+    pub fn render_at(&self, source: &str, origin: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[start..].find('\n').map(|i| i + start).unwrap_or_else(|| source.len());
+        let end = self.span.end.max(start).min(line_end);
+        let (line, col) = self.position(source);
+
+        let pad = " ".repeat(line.to_string().len());
+        let caret = format!("{}{}", " ".repeat(col - 1), "^".repeat(source[start..end].chars().count().max(1)));
+        format!("error: {}\n{}--> {}{}:{}\n{} |\n{} | {}\n{} | {}",
+                self, pad, origin, line, col, pad, line, &source[line_start..line_end], pad, caret)
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let found = self.found.as_deref().unwrap_or("");
+        match self.kind {
+            ErrorKind::InvalidCharacter => write!(f, "invalid character {}", found)?,
+            ErrorKind::InvalidNumber => write!(f, "invalid number {}", found)?,
+            ErrorKind::UnterminatedString => f.write_str("unterminated string")?,
+            ErrorKind::UnexpectedToken => write!(f, "unexpected {}", found)?,
+            ErrorKind::UnexpectedEnd => f.write_str("unexpected end of input")?,
+            ErrorKind::ChainedComparison => f.write_str("comparisons can not be chained")?,
+            ErrorKind::ExpectedCondition => f.write_str("expected condition, found numeric expression")?,
+            ErrorKind::ExpectedNumber => f.write_str("expected numeric expression, found condition")?,
+        }
+        match self.expected.split_last() {
+            Some((last, [])) => write!(f, ", expected {}", last),
+            Some((last, rest)) => write!(f, ", expected {} or {}", rest.join(", "), last),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for SyntaxError {}
+
+/// Line and column of a byte offset, both counted from 1
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let start = offset.min(source.len());
+    let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = source[..start].matches('\n').count() + 1;
+    (line, source[line_start..start].chars().count() + 1)
+}
+
+/// Every syntax error found in one rule, in source order
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxErrors(pub Vec<SyntaxError>);
+
+impl SyntaxErrors {
+    pub fn render_at(&self, source: &str, origin: &str) -> String {
+        let all: Vec<String> = self.0.iter().map(|e| e.render_at(source, origin)).collect();
+        all.join("\n")
+    }
+}
+
+impl fmt::Display for SyntaxErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let all: Vec<String> = self.0.iter().map(|e| e.to_string()).collect();
+        f.write_str(&all.join("\n"))
+    }
+}
+
+impl Error for SyntaxErrors {}
+
+impl From<SyntaxError> for SyntaxErrors {
+    fn from(e: SyntaxError) -> SyntaxErrors {
+        SyntaxErrors(vec![e])
+    }
+}
+
+/// Error of the rule at index `rule` of a batch, `syntax` is set when it comes from the parser
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleError {
+    pub rule: usize,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syntax: Option<SyntaxError>,
+}
+
+impl RuleError {
+    /// One error per syntax error of the rule, or a single one carrying the message
+    pub fn from_error(rule: usize, e: &anyhow::Error) -> Vec<RuleError> {
+        let syntax = match e.downcast_ref::<SyntaxErrors>() {
+            Some(SyntaxErrors(all)) => all.clone(),
+            None => e.downcast_ref::<SyntaxError>().cloned().into_iter().collect(),
+        };
+        if syntax.is_empty() {
+            return vec![RuleError { rule, message: e.to_string(), syntax: None }];
+        }
+        syntax.into_iter().map(|se| RuleError { rule, message: se.to_string(), syntax: Some(se) }).collect()
+    }
+
+    /// `render_at` of the syntax error, other errors only name the origin
+    pub fn render_at(&self, source: &str, origin: &str) -> String {
+        match &self.syntax {
+            Some(se) => se.render_at(source, origin),
+            None => format!("error: {}\n --> {}", self.message, origin.trim_end_matches(':')),
+        }
+    }
+}
+
+/// Renders the errors of a batch one after the other, `field` names the batch in the
+/// origin, `exprs` gives `exprs[3]:1:5`
+pub fn render_all<S: AsRef<str>>(errors: &[RuleError], sources: &[S], field: &str) -> String {
+    let all: Vec<String> = errors.iter()
+        .map(|e| e.render_at(sources[e.rule].as_ref(), &format!("{}[{}]:", field, e.rule)))
+        .collect();
+    all.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::tokenize::Span;
+    use super::{ErrorKind, RuleError, SyntaxError, SyntaxErrors};
+
+    #[test]
+    fn test_display() {
+        let e = SyntaxError::new(ErrorKind::UnexpectedToken, Span::new(5, 6))
+            .found("`)`")
+            .expected(&["identifier", "number", "`(`"]);
+        assert_eq!("unexpected `)`, expected identifier, number or `(`", e.to_string());
+        assert_eq!("unexpected end of input, expected `=>`",
+                   SyntaxError::new(ErrorKind::UnexpectedEnd, Span::new(3, 3)).expected(&["`=>`"]).to_string());
+        assert_eq!("comparisons can not be chained", SyntaxError::new(ErrorKind::ChainedComparison, Span::new(0, 1)).to_string());
+    }
+
+    #[test]
+    fn test_render() {
+        let e = SyntaxError::new(ErrorKind::UnexpectedToken, Span::new(5, 7)).found("`&&`");
+        assert_eq!("error: unexpected `&&`\n --> 1:6\n  |\n1 | A && && B\n  |      ^^", e.render("A && && B"));
+
+        let source = "A => H = M\nB && é ~ C";
+        let e = SyntaxError::new(ErrorKind::InvalidCharacter, Span::new(19, 20)).found("`~`");
+        assert_eq!((2, 8), e.position(source));
+        assert_eq!("error: invalid character `~`\n --> 2:8\n  |\n2 | B && é ~ C\n  |        ^", e.render(source));
+
+        let e = SyntaxError::new(ErrorKind::UnexpectedEnd, Span::new(4, 4));
+        assert_eq!("error: unexpected end of input\n --> 1:5\n  |\n1 | A &&\n  |     ^", e.render("A &&"));
+        assert_eq!("error: unexpected end of input\n --> exprs[2]:1:5\n  |\n1 | A &&\n  |     ^", e.render_at("A &&", "exprs[2]:"));
+    }
+
+    #[test]
+    fn test_rule_errors() {
+        let errors = SyntaxErrors(vec![
+            SyntaxError::new(ErrorKind::InvalidCharacter, Span::new(2, 3)).found("`~`"),
+            SyntaxError::new(ErrorKind::UnexpectedEnd, Span::new(10, 10)).expected(&["category name"]),
+        ]);
+        let all = RuleError::from_error(4, &errors.into());
+        assert_eq!(2, all.len());
+        assert_eq!((4, "unexpected end of input, expected category name"), (all[1].rule, all[1].message.as_str()));
+        assert_eq!(Some(Span::new(10, 10)), all[1].syntax.as_ref().map(|se| se.span));
+
+        let single = SyntaxError::new(ErrorKind::ChainedComparison, Span::new(0, 1));
+        assert_eq!(1, RuleError::from_error(0, &single.into()).len());
+
+        let other = RuleError::from_error(1, &anyhow!("category X is not declared"));
+        assert_eq!(None, other[0].syntax);
+        assert_eq!("error: category X is not declared\n --> exprs[1]", other[0].render_at("A => H = X", "exprs[1]:"));
+    }
+}
@@ -1,12 +1,15 @@
 use anyhow::Result;
-use crate::lib::tokenize::{tokenize, Token};
+use std::fmt;
+use crate::lib::tokenize::{tokenize_all, Token, Span};
+use crate::lib::error::{ErrorKind, SyntaxError, SyntaxErrors};
 use crate::lib::expr::Expr::{Base2, Base1};
 use crate::lib::base1::Base1Expr;
 use crate::lib::base2::Base2Expr;
 use crate::lib::output;
 use crate::lib::scope::Scope;
+use crate::lib::functions::Functions;
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) enum BaseOutput {
     F64(f64),
     Output(Option<output::H>)
@@ -19,19 +22,38 @@ pub enum Expr {
 }
 
 impl Expr {
+    /// Parses an expression which may only call built-in functions
     pub fn from_str(s: &str) -> Result<Expr> {
-        let tokens = tokenize(s)?;
-        match tokens.get(0) {
-            Some(Token::H) => Ok(Base2(Base2Expr::build(&tokens)?)),
-            Some(_) => Ok(Base1(Base1Expr::build(&tokens)?)),
-            _ => return Err(anyhow!("invalid token string"))
+        Expr::from_str_with(s, &Functions::default())
+    }
+
+    /// Parses an expression resolving function calls against the registry.
+    /// Syntax errors are returned together as `SyntaxErrors`, when some characters
+    /// can not be tokenized only those are reported
+    pub fn from_str_with(s: &str, fns: &Functions) -> Result<Expr> {
+        let (tokens, errors) = tokenize_all(s);
+        if !errors.is_empty() {
+            return Err(SyntaxErrors(errors).into());
+        }
+        match tokens.first().map(|t| &t.token) {
+            Some(Token::H) => Ok(Base2(Base2Expr::build(&tokens, fns)?)),
+            Some(_) => Ok(Base1(Base1Expr::build(&tokens, fns)?)),
+            None => Err(SyntaxErrors::from(SyntaxError::new(ErrorKind::UnexpectedEnd, Span::default()).expected(&["condition", "`H`"])).into()),
         }
     }
 
-    fn run(&self, s: Scope) -> Result<BaseOutput> {
+    /// Category produced by a Base1 rule or consumed by a Base2 rule
+    pub fn category(&self) -> &output::H {
+        match self {
+            Base1(exp) => exp.output(),
+            Base2(exp) => exp.output(),
+        }
+    }
+
+    fn run(&self, s: &Scope) -> Result<BaseOutput> {
         match self{
             Base1(exp) => {
-                return Ok(BaseOutput::Output(exp.run(s)));
+                Ok(BaseOutput::Output(exp.run(s)?))
             },
             Base2(exp) => {
                 Ok(BaseOutput::F64(exp.run(s)?))
@@ -40,6 +62,15 @@ impl Expr {
     }
 }
 
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Base1(exp) => exp.fmt(f),
+            Base2(exp) => exp.fmt(f),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::lib::expr::Expr;
@@ -51,8 +82,8 @@ mod test {
     fn test_expr1() {
         let rs = Expr::from_str("A && B && !C => H = M").unwrap();
         assert_eq!(
-            rs.run(Scope::abc(true, true, false)).unwrap(),
-            BaseOutput::Output(Some(output::H::M))
+            rs.run(&Scope::abc(true, true, false)).unwrap(),
+            BaseOutput::Output(Some(output::H::new("M")))
         );
     }
 
@@ -60,8 +91,17 @@ mod test {
     fn test_expr2() {
         let rs = Expr::from_str("H = P => K = D + (D * (E - F) / 25.5)").unwrap();
         assert_eq!(
-            rs.run(Scope::def(1.0, 1, 1)).unwrap(),
+            rs.run(&Scope::def(1.0, 1, 1)).unwrap(),
             BaseOutput::F64(1.0)
         )
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_expr2_unary() {
+        let rs = Expr::from_str("H = T => K = -D + 5 * -(E - -F)").unwrap();
+        assert_eq!(
+            rs.run(&Scope::def(1.0, 2, 1)).unwrap(),
+            BaseOutput::F64(-16.0)
+        )
+    }
+}
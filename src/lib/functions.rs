@@ -0,0 +1,292 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Number of arguments a function accepts
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exact(a) => n == a,
+            Arity::Range(min, max) => n >= min && n <= max,
+            Arity::AtLeast(min) => n >= min,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        match self {
+            Arity::Exact(a) => write!(f, "{} {}", a, plural(*a)),
+            Arity::Range(min, max) => write!(f, "{} to {} {}", min, max, plural(*max)),
+            Arity::AtLeast(min) => write!(f, "at least {} {}", min, plural(*min)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Builtin {
+    Min,
+    Max,
+    Abs,
+    Round,
+    Floor,
+    Ceil,
+    Pow,
+    Sqrt,
+    Clamp,
+}
+
+impl Builtin {
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        match name {
+            "min" => Some(Builtin::Min),
+            "max" => Some(Builtin::Max),
+            "abs" => Some(Builtin::Abs),
+            "round" => Some(Builtin::Round),
+            "floor" => Some(Builtin::Floor),
+            "ceil" => Some(Builtin::Ceil),
+            "pow" => Some(Builtin::Pow),
+            "sqrt" => Some(Builtin::Sqrt),
+            "clamp" => Some(Builtin::Clamp),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::Abs => "abs",
+            Builtin::Round => "round",
+            Builtin::Floor => "floor",
+            Builtin::Ceil => "ceil",
+            Builtin::Pow => "pow",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Clamp => "clamp",
+        }
+    }
+
+    pub fn arity(self) -> Arity {
+        match self {
+            Builtin::Min | Builtin::Max => Arity::AtLeast(1),
+            Builtin::Abs | Builtin::Floor | Builtin::Ceil | Builtin::Sqrt => Arity::Exact(1),
+            Builtin::Round => Arity::Range(1, 2),
+            Builtin::Pow => Arity::Exact(2),
+            Builtin::Clamp => Arity::Exact(3),
+        }
+    }
+
+    /// Arguments are expected to be checked against `arity` at build time
+    pub fn call(self, args: &[f64]) -> Result<f64> {
+        let v = match self {
+            Builtin::Min => args.iter().cloned().fold(f64::INFINITY, f64::min),
+            Builtin::Max => args.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            Builtin::Abs => args[0].abs(),
+            Builtin::Floor => args[0].floor(),
+            Builtin::Ceil => args[0].ceil(),
+            Builtin::Round => {
+                let digits = args.get(1).cloned().unwrap_or(0.0);
+                if digits.fract() != 0.0 {
+                    return Err(anyhow!("round: digits must be an integer, found {}", digits));
+                }
+                let factor = 10f64.powi(digits as i32);
+                (args[0] * factor).round() / factor
+            },
+            Builtin::Sqrt => {
+                if args[0] < 0.0 {
+                    return Err(anyhow!("sqrt: domain error, argument {} is negative", args[0]));
+                }
+                args[0].sqrt()
+            },
+            Builtin::Pow => args[0].powf(args[1]),
+            Builtin::Clamp => {
+                if args[1] > args[2] {
+                    return Err(anyhow!("clamp: domain error, min {} is greater than max {}", args[1], args[2]));
+                }
+                args[0].max(args[1]).min(args[2])
+            },
+        };
+        Ok(v)
+    }
+}
+
+pub type Callback = dyn Fn(&[f64]) -> Result<f64> + Send + Sync;
+
+/// Function registered from Rust code, cheap to clone and safe to share between threads
+#[derive(Clone)]
+pub struct Custom {
+    name: String,
+    arity: Arity,
+    f: Arc<Callback>,
+}
+
+impl fmt::Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Custom")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl PartialEq for Custom {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.arity == other.arity && Arc::ptr_eq(&self.f, &other.f)
+    }
+}
+
+/// Function resolved at build time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Function {
+    Builtin(Builtin),
+    Custom(Custom),
+}
+
+impl Function {
+    pub fn name(&self) -> &str {
+        match self {
+            Function::Builtin(f) => f.name(),
+            Function::Custom(f) => &f.name,
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        match self {
+            Function::Builtin(f) => f.arity(),
+            Function::Custom(f) => f.arity,
+        }
+    }
+
+    pub fn call(&self, args: &[f64]) -> Result<f64> {
+        let v = match self {
+            Function::Builtin(f) => f.call(args)?,
+            Function::Custom(f) => (f.f)(args)?,
+        };
+        if !v.is_finite() {
+            return Err(anyhow!("{}: domain error, result is not a finite number", self.name()));
+        }
+        Ok(v)
+    }
+}
+
+/// Registry of custom functions on top of the built-in ones
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Functions {
+    custom: HashMap<String, Custom>,
+}
+
+impl Functions {
+    pub fn register<F>(&mut self, name: &str, arity: Arity, f: F) -> Result<()>
+        where F: Fn(&[f64]) -> Result<f64> + Send + Sync + 'static {
+        if Builtin::lookup(name).is_some() {
+            return Err(anyhow!("function {} is built-in", name));
+        }
+        let valid = name.chars().enumerate().all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if name.is_empty() || !valid {
+            return Err(anyhow!("invalid function name {:?}", name));
+        }
+        self.custom.insert(name.to_string(), Custom {
+            name: name.to_string(),
+            arity,
+            f: Arc::new(f),
+        });
+        Ok(())
+    }
+
+    /// Finds the function and checks the number of arguments
+    pub fn resolve(&self, name: &str, argc: usize) -> Result<Function> {
+        let f = match (Builtin::lookup(name), self.custom.get(name)) {
+            (Some(f), _) => Function::Builtin(f),
+            (None, Some(f)) => Function::Custom(f.clone()),
+            (None, None) => return Err(anyhow!("unknown function {}", name)),
+        };
+        if !f.arity().accepts(argc) {
+            return Err(anyhow!("function {} expects {}, found {}", name, f.arity(), argc));
+        }
+        Ok(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Arity, Builtin, Functions, Function};
+
+    #[test]
+    fn test_arity() {
+        assert!(Arity::Exact(2).accepts(2));
+        assert!(!Arity::Exact(2).accepts(3));
+        assert!(Arity::Range(1, 2).accepts(1));
+        assert!(!Arity::Range(1, 2).accepts(0));
+        assert!(Arity::AtLeast(1).accepts(5));
+        assert_eq!("1 to 2 arguments", Arity::Range(1, 2).to_string());
+    }
+
+    #[test]
+    fn test_call() {
+        let values = vec![
+            ("min", vec![3.0, -1.0, 2.0], -1.0),
+            ("max", vec![0.0, 5.5], 5.5),
+            ("abs", vec![-2.5], 2.5),
+            ("round", vec![2.5], 3.0),
+            ("round", vec![1.23456, 2.0], 1.23),
+            ("round", vec![1250.0, -2.0], 1300.0),
+            ("floor", vec![-1.5], -2.0),
+            ("ceil", vec![1.1], 2.0),
+            ("pow", vec![2.0, 10.0], 1024.0),
+            ("sqrt", vec![16.0], 4.0),
+            ("clamp", vec![120.0, 0.0, 100.0], 100.0),
+            ("clamp", vec![-5.0, 0.0, 100.0], 0.0),
+        ];
+        for (name, args, expected) in values {
+            let f = Builtin::lookup(name).unwrap();
+            assert!(f.arity().accepts(args.len()));
+            assert_eq!(expected, f.call(&args).unwrap(), "{} {:?}", name, args);
+        }
+    }
+
+    #[test]
+    fn test_domain_errors() {
+        assert_eq!("sqrt: domain error, argument -4 is negative",
+                   Builtin::Sqrt.call(&[-4.0]).unwrap_err().to_string());
+        assert_eq!("pow: domain error, result is not a finite number",
+                   Function::Builtin(Builtin::Pow).call(&[-8.0, 0.5]).unwrap_err().to_string());
+        assert_eq!("clamp: domain error, min 10 is greater than max 1",
+                   Builtin::Clamp.call(&[5.0, 10.0, 1.0]).unwrap_err().to_string());
+        assert!(Builtin::Round.call(&[1.0, 0.5]).is_err());
+        assert!(Builtin::lookup("tax").is_none());
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut fns = Functions::default();
+        fns.register("tax", Arity::Exact(2), |args| Ok(args[0] * args[1] / 100.0)).unwrap();
+        fns.register("discount_tier", Arity::Exact(1), |args| match args[0] {
+            v if v < 0.0 => Err(anyhow!("discount_tier: negative count")),
+            v if v >= 10.0 => Ok(2.0),
+            _ => Ok(1.0),
+        }).unwrap();
+
+        let tax = fns.resolve("tax", 2).unwrap();
+        assert_eq!(5.0, tax.call(&[50.0, 10.0]).unwrap());
+        assert_eq!(2.0, fns.resolve("discount_tier", 1).unwrap().call(&[12.0]).unwrap());
+        assert!(fns.resolve("discount_tier", 1).unwrap().call(&[-1.0]).is_err());
+        assert_eq!(Function::Builtin(Builtin::Max), fns.resolve("max", 3).unwrap());
+
+        assert_eq!("function tax expects 2 arguments, found 1", fns.resolve("tax", 1).unwrap_err().to_string());
+        assert_eq!("unknown function vat", fns.resolve("vat", 1).unwrap_err().to_string());
+        assert_eq!("function round is built-in",
+                   fns.register("round", Arity::Exact(1), |a| Ok(a[0])).unwrap_err().to_string());
+        assert!(fns.register("1tax", Arity::Exact(1), |a| Ok(a[0])).is_err());
+
+        assert_eq!(fns.clone(), fns);
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::lib::analysis::Coverage;
+use crate::lib::app::{App, Strategy};
+use crate::lib::rule::RuleSource;
+
+/// Rule set as it was after a change, never modified afterwards
+#[derive(Debug)]
+pub struct Version {
+    pub number: u64,
+    pub change: String,
+    pub created: SystemTime,
+    pub app: Arc<App>,
+    coverage: Mutex<Option<std::result::Result<Coverage, String>>>,
+}
+
+/// Summary of a version for listings
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VersionInfo {
+    pub number: u64,
+    pub change: String,
+    /// Seconds since the unix epoch
+    pub created: u64,
+    pub rules: usize,
+}
+
+impl Version {
+    pub fn new(number: u64, change: &str, app: Arc<App>) -> Version {
+        Version {
+            number,
+            change: change.to_string(),
+            created: SystemTime::now(),
+            app,
+            coverage: Mutex::new(None),
+        }
+    }
+
+    /// `App::coverage` of the rules, computed on the first call and kept with the version
+    pub fn coverage(&self) -> Result<Coverage> {
+        let mut coverage = self.coverage.lock().unwrap_or_else(|e| e.into_inner());
+        coverage.get_or_insert_with(|| self.app.coverage().map_err(|e| e.to_string()))
+            .clone()
+            .map_err(|e| anyhow!(e))
+    }
+
+    pub fn info(&self) -> VersionInfo {
+        VersionInfo {
+            number: self.number,
+            change: self.change.clone(),
+            created: self.created.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            rules: self.app.rules().len(),
+        }
+    }
+}
+
+/// Rule before and after a change, matched by id
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Changed {
+    pub before: RuleSource,
+    pub after: RuleSource,
+}
+
+/// Differences between two rule sets, rules are compared by id
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Diff {
+    pub categories_added: Vec<String>,
+    pub categories_removed: Vec<String>,
+    /// Strategy before and after when it changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<(Strategy, Strategy)>,
+    pub added: Vec<RuleSource>,
+    pub removed: Vec<RuleSource>,
+    pub changed: Vec<Changed>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        *self == Diff::default()
+    }
+}
+
+/// What changed going from `from` to `to`, rules in the order of the version they are in
+pub fn diff(from: &App, to: &App) -> Diff {
+    let (a, b) = (from.rule_set(), to.rule_set());
+    let mut d = Diff {
+        categories_added: b.categories.iter().filter(|c| !a.categories.contains(c)).cloned().collect(),
+        categories_removed: a.categories.iter().filter(|c| !b.categories.contains(c)).cloned().collect(),
+        ..Diff::default()
+    };
+    if a.strategy != b.strategy {
+        d.strategy = Some((a.strategy, b.strategy));
+    }
+    for r in &a.rules {
+        match b.rules.iter().find(|x| x.id == r.id) {
+            Some(after) if after != r => d.changed.push(Changed {
+                before: r.clone(),
+                after: after.clone(),
+            }),
+            Some(_) => {},
+            None => d.removed.push(r.clone()),
+        }
+    }
+    d.added = b.rules.iter().filter(|r| !a.rules.iter().any(|x| x.id == r.id)).cloned().collect();
+    d
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use crate::lib::app::{App, Strategy};
+    use super::{diff, Version};
+
+    #[test]
+    fn test_diff() {
+        let before = App::default();
+        assert!(diff(&before, &before).is_empty());
+
+        let after = before
+            .category("GOLD")
+            .with_strategy(Strategy::CollectAll)
+            .add("A && !B => H = GOLD").unwrap()
+            .add("!A  &&  B && C => H = T").unwrap()
+            .remove_rule(5).unwrap();
+        let d = diff(&before, &after);
+        assert_eq!(vec!["GOLD"], d.categories_added);
+        assert!(d.categories_removed.is_empty());
+        assert_eq!(Some((Strategy::FirstMatch, Strategy::CollectAll)), d.strategy);
+        assert_eq!(vec![Some(7)], d.added.iter().map(|r| r.id).collect::<Vec<_>>());
+        assert_eq!(vec![Some(5)], d.removed.iter().map(|r| r.id).collect::<Vec<_>>());
+        assert_eq!(1, d.changed.len());
+        assert_eq!(("!A && B && C => H = T", "!A  &&  B && C => H = T"),
+                   (d.changed[0].before.source.as_str(), d.changed[0].after.source.as_str()));
+
+        let back = diff(&after, &before);
+        assert_eq!(vec!["GOLD"], back.categories_removed);
+        assert_eq!(vec![Some(5)], back.added.iter().map(|r| r.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_coverage() {
+        let version = Version::new(1, "initial rules", Arc::new(App::default()));
+        let coverage = version.coverage().unwrap();
+        assert_eq!(2, coverage.gaps.len());
+        assert_eq!(coverage, version.coverage().unwrap());
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use crate::lib::expr::Expr;
+use crate::lib::functions::Functions;
+use crate::lib::parser::{BinOp, CmpOp, LogicOp, Node, UnOp};
+use crate::lib::rule::{Rule, RuleSource};
+use crate::lib::tokenize::is_ident;
+
+/// JSON Logic operators the rules can not express
+const UNSUPPORTED: [&str; 16] = ["if", "?:", "in", "cat", "substr", "merge", "map", "filter", "reduce", "all", "none",
+                                 "some", "missing", "missing_some", "log", "%"];
+
+/// Rule in the form a JSON Logic editor posts and shows it: a Base1 rule has a `condition`,
+/// a Base2 rule a `formula`, e.g. `{"category": "M", "condition": {"and": [{"var": "A"}, {"!": {"var": "B"}}]}}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub priority: i64,
+    pub category: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formula: Option<Value>,
+}
+
+impl LogicRule {
+    /// Category and condition or formula of the expression, without metadata
+    pub fn from_expr(e: &Expr) -> LogicRule {
+        let (condition, formula) = match e {
+            Expr::Base1(e) => (Some(to_logic(e.condition())), None),
+            Expr::Base2(e) => (None, Some(to_logic(e.formula()))),
+        };
+        LogicRule {
+            id: None,
+            name: None,
+            owner: None,
+            description: None,
+            priority: 0,
+            category: e.category().name().to_string(),
+            condition,
+            formula,
+        }
+    }
+
+    pub fn from_rule(r: &Rule) -> LogicRule {
+        LogicRule {
+            id: Some(r.id()),
+            name: r.name().map(String::from),
+            owner: r.owner().map(String::from),
+            description: r.description().map(String::from),
+            priority: r.priority(),
+            ..LogicRule::from_expr(r.expr())
+        }
+    }
+
+    /// Rule text with the metadata, conversion errors name the operand: `condition.and[1]: ...`
+    pub fn to_source(&self) -> Result<RuleSource> {
+        let source = match (&self.condition, &self.formula) {
+            (Some(c), None) => format!("{} => H = {}", from_logic(c, "condition")?, self.category),
+            (None, Some(f)) => format!("H = {} => K = {}", self.category, from_logic(f, "formula")?),
+            _ => return Err(anyhow!("a rule has either a `condition` or a `formula`")),
+        };
+        Ok(RuleSource {
+            id: self.id,
+            name: self.name.clone(),
+            owner: self.owner.clone(),
+            description: self.description.clone(),
+            source,
+            priority: self.priority,
+        })
+    }
+
+    /// Builds the expression, calls resolve to the functions given
+    pub fn to_expr(&self, fns: &Functions) -> Result<Expr> {
+        Expr::from_str_with(&self.to_source()?.source, fns)
+    }
+}
+
+fn number(v: f64) -> Value {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        return json!(v as i64);
+    }
+    json!(v)
+}
+
+/// JSON Logic of a condition or formula. `&&`, `||`, `+` and `*` chains are one operation,
+/// comparisons are `<`, `<=`, `>`, `>=`, `==` and `!=` and function calls operations named after the function
+pub fn to_logic(n: &Node) -> Value {
+    let op = |name: &str, args: Vec<Value>| {
+        let mut m = Map::new();
+        m.insert(name.to_string(), Value::Array(args));
+        Value::Object(m)
+    };
+    let chain = |n: &Node| n.chain().into_iter().map(to_logic).collect();
+    match n {
+        Node::Var(name) => json!({"var": name}),
+        Node::Const(v) => number(*v),
+        Node::Unary(UnOp::Neg, x) => match &**x {
+            Node::Const(v) => number(-v),
+            _ => op("-", vec![to_logic(x)]),
+        },
+        Node::Unary(UnOp::Pos, x) => op("+", vec![to_logic(x)]),
+        Node::Not(x) => op("!", vec![to_logic(x)]),
+        Node::Logic(LogicOp::And, ..) => op("and", chain(n)),
+        Node::Logic(LogicOp::Or, ..) => op("or", chain(n)),
+        Node::Binary(BinOp::Add, ..) => op("+", chain(n)),
+        Node::Binary(BinOp::Mul, ..) => op("*", chain(n)),
+        Node::Binary(BinOp::Sub, l, r) => op("-", vec![to_logic(l), to_logic(r)]),
+        Node::Binary(BinOp::Div, l, r) => op("/", vec![to_logic(l), to_logic(r)]),
+        Node::Compare(cmp, l, r) => {
+            let name = match cmp {
+                CmpOp::Lt => "<",
+                CmpOp::Le => "<=",
+                CmpOp::Gt => ">",
+                CmpOp::Ge => ">=",
+                CmpOp::Eq => "==",
+                CmpOp::Ne => "!=",
+            };
+            op(name, vec![to_logic(l), to_logic(r)])
+        },
+        Node::Call(name, args) => op(name, args.iter().map(to_logic).collect()),
+    }
+}
+
+fn kind(v: &Value) -> String {
+    match v {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => format!("boolean `{}`", b),
+        Value::Number(n) => format!("number `{}`", n),
+        Value::String(s) => format!("string {:?}", s),
+        Value::Array(_) => "array".to_string(),
+        Value::Object(_) => "object".to_string(),
+    }
+}
+
+/// Tree of a JSON Logic condition or formula, `path` names the value in errors.
+/// Besides the operators `to_logic` writes `===`, `!==`, `!!`, `min`, `max`, `<` and `<=` with
+/// three operands (between), unary sugar like `{"!": {"var": "A"}}` and conditions compared with
+/// `true` or `false` are read, other operations are calls of the function with their name
+pub fn from_logic(v: &Value, path: &str) -> Result<Node> {
+    let op = match v {
+        Value::Number(n) => {
+            let v = n.as_f64().ok_or_else(|| anyhow!("{}: number `{}` is out of range", path, n))?;
+            if v < 0.0 {
+                return Ok(Node::Unary(UnOp::Neg, Box::new(Node::Const(-v))));
+            }
+            return Ok(Node::Const(v));
+        },
+        Value::Object(m) if m.len() == 1 => m.iter().next().unwrap(),
+        Value::Object(m) => return Err(anyhow!("{}: an operation has exactly one operator, found {}", path, m.len())),
+        Value::Bool(b) => return Err(anyhow!("{}: boolean `{}` is only supported as operand of `==` or `!=`", path, b)),
+        v => return Err(anyhow!("{}: {} is not supported, expected a number or an operation", path, kind(v))),
+    };
+    let (name, args) = op;
+    let args: Vec<&Value> = match args {
+        Value::Array(all) => all.iter().collect(),
+        single => vec![single],
+    };
+    let here = |i: usize| format!("{}.{}[{}]", path, name, i);
+    let arg = |i: usize| -> Result<Box<Node>> { Ok(Box::new(from_logic(args[i], &here(i))?)) };
+    let count = |min: usize, max: usize| {
+        if args.len() < min || args.len() > max {
+            let expected = match (min, max) {
+                (a, b) if a == b => a.to_string(),
+                (a, usize::MAX) => format!("at least {}", a),
+                (a, b) => format!("{} or {}", a, b),
+            };
+            return Err(anyhow!("{}: `{}` needs {} operands, got {}", path, name, expected, args.len()));
+        }
+        Ok(())
+    };
+    let fold = |f: &dyn Fn(Box<Node>, Box<Node>) -> Node| -> Result<Node> {
+        (1..args.len()).try_fold(*arg(0)?, |l, i| Ok(f(Box::new(l), arg(i)?)))
+    };
+    let compare = |op: CmpOp| -> Result<Node> {
+        count(2, 2)?;
+        // `{"==": [{"var": "A"}, true]}` is the condition itself, compared with false its negation
+        let boolean = match (args[0], args[1]) {
+            (_, Value::Bool(b)) => Some((0, *b)),
+            (Value::Bool(b), _) => Some((1, *b)),
+            _ => None,
+        };
+        match boolean {
+            Some((i, b)) if op == CmpOp::Eq || op == CmpOp::Ne => {
+                let condition = *arg(i)?;
+                Ok(if b == (op == CmpOp::Eq) { condition } else { Node::Not(Box::new(condition)) })
+            },
+            _ => Ok(Node::Compare(op, arg(0)?, arg(1)?)),
+        }
+    };
+    Ok(match name.as_str() {
+        "var" => {
+            count(1, 1).map_err(|_| anyhow!("{}: `var` with a default value is not supported", path))?;
+            match args[0] {
+                Value::String(s) if is_ident(s) => Node::Var(s.clone()),
+                v => return Err(anyhow!("{}: `var` needs an input name, found {}", path, kind(v))),
+            }
+        },
+        "!" => {
+            count(1, 1)?;
+            Node::Not(arg(0)?)
+        },
+        "!!" => {
+            count(1, 1)?;
+            Node::Not(Box::new(Node::Not(arg(0)?)))
+        },
+        "and" | "or" => {
+            count(1, usize::MAX)?;
+            let op = if name == "and" { LogicOp::And } else { LogicOp::Or };
+            fold(&|l, r| Node::Logic(op, l, r))?
+        },
+        "<" | "<=" if args.len() == 3 => {
+            let op = if name == "<" { CmpOp::Lt } else { CmpOp::Le };
+            let low = Node::Compare(op, arg(0)?, arg(1)?);
+            Node::Logic(LogicOp::And, Box::new(low), Box::new(Node::Compare(op, arg(1)?, arg(2)?)))
+        },
+        "<" => compare(CmpOp::Lt)?,
+        "<=" => compare(CmpOp::Le)?,
+        ">" => compare(CmpOp::Gt)?,
+        ">=" => compare(CmpOp::Ge)?,
+        "==" | "===" => compare(CmpOp::Eq)?,
+        "!=" | "!==" => compare(CmpOp::Ne)?,
+        "+" if args.len() == 1 => Node::Unary(UnOp::Pos, arg(0)?),
+        "+" => {
+            count(1, usize::MAX)?;
+            fold(&|l, r| Node::Binary(BinOp::Add, l, r))?
+        },
+        "*" => {
+            count(2, usize::MAX)?;
+            fold(&|l, r| Node::Binary(BinOp::Mul, l, r))?
+        },
+        "-" if args.len() == 1 => Node::Unary(UnOp::Neg, arg(0)?),
+        "-" => {
+            count(1, 2)?;
+            Node::Binary(BinOp::Sub, arg(0)?, arg(1)?)
+        },
+        "/" => {
+            count(2, 2)?;
+            Node::Binary(BinOp::Div, arg(0)?, arg(1)?)
+        },
+        name if UNSUPPORTED.contains(&name) => return Err(anyhow!("{}: unsupported operator `{}`", path, name)),
+        name if is_ident(name) => Node::Call(name.to_string(), (0..args.len()).map(|i| Ok(*arg(i)?)).collect::<Result<_>>()?),
+        name => return Err(anyhow!("{}: unknown operator `{}`", path, name)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::app::App;
+    use crate::lib::expr::Expr;
+    use crate::lib::scope::Scope;
+    use serde_json::{json, Value};
+    use super::{from_logic, LogicRule};
+
+    #[test]
+    fn test_round_trip() {
+        let app = App::default()
+            .add("(A || B) && !(C && D >= -2) && max(D, E) != 3 => H = M").unwrap()
+            .add("H = P => K = -D * (E + F + 1) / +2.5 - (E - F)").unwrap();
+        for r in app.rules() {
+            let logic = LogicRule::from_rule(r);
+            assert_eq!(r.expr(), &logic.to_expr(&Default::default()).unwrap(), "{}", r.source());
+        }
+        let json = |e: &str| serde_json::to_string(&LogicRule::from_expr(&Expr::from_str(e).unwrap())).unwrap();
+        assert_eq!(r#"{"priority":0,"category":"M","condition":{"and":[{"var":"A"},{"var":"B"},{"!":[{"var":"C"}]}]}}"#,
+                   json("A && B && !C => H = M"));
+        assert_eq!(r#"{"priority":0,"category":"T","formula":{"-":[{"var":"D"},{"/":[{"*":[{"var":"D"},{"var":"F"}]},30]}]}}"#,
+                   json("H = T => K = D - (D * F / 30)"));
+    }
+
+    #[test]
+    fn test_from_logic() {
+        let condition: LogicRule = serde_json::from_str(r#"{"category": "M", "condition":
+            {"and": [{"!!": {"var": "is_vip"}}, {"<=": [10, {"var": "total"}, 100]}, {"===": [{"var": "items"}, 3]}]}}"#).unwrap();
+        assert_eq!("!!is_vip && (10 <= total && total <= 100) && items == 3 => H = M", condition.to_source().unwrap().source);
+        let formula: LogicRule = serde_json::from_str(r#"{"category": "M", "formula": {"+": [{"min": [{"var": "total"}, 50]}, {"-": 1.5}]}}"#).unwrap();
+        assert_eq!("H = M => K = min(total, 50) + -1.5", formula.to_source().unwrap().source);
+
+        let app = App::new().category("M").add_logic(&[condition, formula]).unwrap();
+        let scope: Scope = serde_json::from_str(r#"{"is_vip": true, "total": 80, "items": 3}"#).unwrap();
+        assert_eq!(48.5, app.run(&scope).unwrap());
+        assert_eq!(app.rules().iter().map(LogicRule::from_rule).collect::<Vec<_>>(), app.logic_rules());
+
+        let source = |s: &str| from_logic(&serde_json::from_str(s).unwrap(), "condition").unwrap().to_string();
+        assert_eq!("A", source(r#"{"==": [{"var": "A"}, true]}"#));
+        assert_eq!("!A", source(r#"{"===": [false, {"var": "A"}]}"#));
+        assert_eq!("!(A && B)", source(r#"{"!=": [{"and": [{"var": "A"}, {"var": "B"}]}, true]}"#));
+        assert_eq!("A", source(r#"{"!==": [{"var": "A"}, false]}"#));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |s: &str| from_logic(&serde_json::from_str(s).unwrap(), "condition").unwrap_err().to_string();
+        assert_eq!("condition.and[1]: unsupported operator `in`", err(r#"{"and": [{"var": "A"}, {"in": ["a", ["a", "b"]]}]}"#));
+        assert_eq!("condition.or[0]: boolean `true` is only supported as operand of `==` or `!=`",
+                   err(r#"{"or": [true, {"var": "A"}]}"#));
+        assert_eq!("condition.<[1]: boolean `false` is only supported as operand of `==` or `!=`",
+                   err(r#"{"<": [{"var": "A"}, false]}"#));
+        assert_eq!("condition.<[1]: `var` with a default value is not supported", err(r#"{"<": [1, {"var": ["D", 0]}]}"#));
+        assert_eq!("condition.!=[0]: `var` needs an input name, found string \"user.age\"", err(r#"{"!=": [{"var": "user.age"}, 1]}"#));
+        assert_eq!("condition: `>` needs 2 operands, got 3", err(r#"{">": [1, 2, 3]}"#));
+        assert_eq!("condition: `-` needs 1 or 2 operands, got 3", err(r#"{"-": [1, 2, 3]}"#));
+        assert_eq!("condition: an operation has exactly one operator, found 2", err(r#"{"var": "A", "!": true}"#));
+        assert_eq!("condition: unknown operator `===>`", err(r#"{"===>": [1, 2]}"#));
+
+        let rule = LogicRule { condition: Some(serde_json::json!({"+": [1, 2]})), ..LogicRule::from_expr(&Expr::from_str("A => H = M").unwrap()) };
+        assert_eq!("expected condition, found numeric expression", rule.to_expr(&Default::default()).unwrap_err().to_string());
+        let add = |rules: &[LogicRule]| App::default().add_logic(rules).unwrap_err().to_string();
+        assert_eq!("error: a rule has either a `condition` or a `formula`\n --> rules[0]",
+                   add(&[LogicRule { formula: Some(serde_json::json!(1)), ..rule.clone() }]));
+        let condition = |c: Value, category: &str| LogicRule { category: category.to_string(), condition: Some(c), ..rule.clone() };
+        assert_eq!("error: expected condition, found numeric expression\n --> rules[0]:1:1\n  |\n1 | 1 + 2 => H = M\n  | ^^^^^\n\
+                    error: category X is not declared\n --> rules[1]",
+                   add(&[condition(json!({"==": [{"+": [1, 2]}, true]}), "M"), condition(json!({"var": "A"}), "X")]));
+    }
+}
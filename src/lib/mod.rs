@@ -1,7 +1,21 @@
 mod tokenize;
+pub(crate) mod error;
 mod base1;
 mod base2;
+mod parser;
+mod rpn;
+pub(crate) mod functions;
 mod output;
 pub(crate) mod scope;
 mod expr;
+pub(crate) mod rule;
+pub(crate) mod analysis;
+pub(crate) mod trace;
+pub(crate) mod history;
+pub(crate) mod sprout;
+pub(crate) mod document;
+pub(crate) mod table;
+pub(crate) mod dmn;
+pub(crate) mod jsonlogic;
+pub(crate) mod store;
 pub(crate) mod app;
\ No newline at end of file
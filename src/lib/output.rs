@@ -1,6 +1,21 @@
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum H {
-    M,
-    P,
-    T
-}
\ No newline at end of file
+use std::fmt;
+
+/// Category of the intermediate decision `H`, declared by name on the `App`
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct H(String);
+
+impl H {
+    pub fn new(name: &str) -> H {
+        H(name.to_string())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for H {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
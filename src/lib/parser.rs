@@ -0,0 +1,594 @@
+use std::fmt;
+use crate::lib::tokenize::{Token, Span, Spanned};
+use crate::lib::error::{ErrorKind, SyntaxError};
+
+/// Typed syntax tree of conditions and formulas
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Var(String),
+    Const(f64),
+    Unary(UnOp, Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Logic(LogicOp, Box<Node>, Box<Node>),
+    Compare(CmpOp, Box<Node>, Box<Node>),
+    Call(String, Vec<Node>),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Pos,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinOp {
+    /// All operators are left associative, `*` and `/` bind tighter than `+` and `-`
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinOp::Add | BinOp::Sub => 4,
+            BinOp::Mul | BinOp::Div => 5,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LogicOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl CmpOp {
+    pub fn apply(self, l: f64, r: f64) -> bool {
+        match self {
+            CmpOp::Lt => l < r,
+            CmpOp::Le => l <= r,
+            CmpOp::Gt => l > r,
+            CmpOp::Ge => l >= r,
+            CmpOp::Eq => l == r,
+            CmpOp::Ne => l != r,
+        }
+    }
+
+    /// Operator holding exactly when this one does not
+    pub fn negate(self) -> CmpOp {
+        match self {
+            CmpOp::Lt => CmpOp::Ge,
+            CmpOp::Le => CmpOp::Gt,
+            CmpOp::Gt => CmpOp::Le,
+            CmpOp::Ge => CmpOp::Lt,
+            CmpOp::Eq => CmpOp::Ne,
+            CmpOp::Ne => CmpOp::Eq,
+        }
+    }
+
+    /// Operator with swapped operands, `a < b` is `b > a`
+    pub fn flip(self) -> CmpOp {
+        match self {
+            CmpOp::Lt => CmpOp::Gt,
+            CmpOp::Le => CmpOp::Ge,
+            CmpOp::Gt => CmpOp::Lt,
+            CmpOp::Ge => CmpOp::Le,
+            CmpOp::Eq | CmpOp::Ne => self,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Infix {
+    Bin(BinOp),
+    Logic(LogicOp),
+    Cmp(CmpOp),
+}
+
+impl Infix {
+    fn from_token(t: &Token) -> Option<Infix> {
+        match t {
+            Token::Plus => Some(Infix::Bin(BinOp::Add)),
+            Token::Minus => Some(Infix::Bin(BinOp::Sub)),
+            Token::Multiple => Some(Infix::Bin(BinOp::Mul)),
+            Token::Divide => Some(Infix::Bin(BinOp::Div)),
+            Token::And => Some(Infix::Logic(LogicOp::And)),
+            Token::Or => Some(Infix::Logic(LogicOp::Or)),
+            Token::Lt => Some(Infix::Cmp(CmpOp::Lt)),
+            Token::Le => Some(Infix::Cmp(CmpOp::Le)),
+            Token::Gt => Some(Infix::Cmp(CmpOp::Gt)),
+            Token::Ge => Some(Infix::Cmp(CmpOp::Ge)),
+            Token::EqEq => Some(Infix::Cmp(CmpOp::Eq)),
+            Token::Ne => Some(Infix::Cmp(CmpOp::Ne)),
+            _ => None,
+        }
+    }
+
+    /// `||` binds weakest, then `&&`, comparisons and arithmetic
+    fn precedence(self) -> u8 {
+        match self {
+            Infix::Logic(LogicOp::Or) => 1,
+            Infix::Logic(LogicOp::And) => 2,
+            Infix::Cmp(_) => 3,
+            Infix::Bin(op) => op.precedence(),
+        }
+    }
+}
+
+impl Node {
+    /// Operands of a `Binary` or `Logic` node with the left side flattened while it applies
+    /// the same operator, `a - b - c` is one list and `a - (b - c)` stays nested
+    pub fn chain(&self) -> Vec<&Node> {
+        let (l, r) = match self {
+            Node::Binary(_, l, r) | Node::Logic(_, l, r) => (&**l, &**r),
+            _ => return vec![self],
+        };
+        let mut operands = match (self, l) {
+            (Node::Binary(a, _, _), Node::Binary(b, _, _)) if a == b => l.chain(),
+            (Node::Logic(a, _, _), Node::Logic(b, _, _)) if a == b => l.chain(),
+            _ => vec![l],
+        };
+        operands.push(r);
+        operands
+    }
+
+    /// Binding power used to decide where `Display` needs parentheses
+    fn precedence(&self) -> u8 {
+        match self {
+            Node::Logic(op, _, _) => Infix::Logic(*op).precedence(),
+            Node::Compare(..) => 3,
+            Node::Binary(op, _, _) => op.precedence(),
+            Node::Unary(..) | Node::Not(_) => 6,
+            Node::Var(_) | Node::Const(_) | Node::Call(..) => 7,
+        }
+    }
+}
+
+/// Prints the tree back as source text with the minimal set of parentheses
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let child = |f: &mut fmt::Formatter, n: &Node, wrap: bool| {
+            if wrap {
+                write!(f, "({})", n)
+            } else {
+                write!(f, "{}", n)
+            }
+        };
+        let prec = self.precedence();
+        match self {
+            Node::Var(name) => write!(f, "{}", name),
+            Node::Const(v) => write!(f, "{}", v),
+            Node::Unary(op, n) => {
+                f.write_str(if *op == UnOp::Neg { "-" } else { "+" })?;
+                child(f, n, n.precedence() < prec)
+            },
+            Node::Not(n) => {
+                f.write_str("!")?;
+                child(f, n, n.precedence() < prec)
+            },
+            Node::Binary(op, l, r) => {
+                let op = match op {
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::Mul => "*",
+                    BinOp::Div => "/",
+                };
+                child(f, l, l.precedence() < prec)?;
+                write!(f, " {} ", op)?;
+                child(f, r, r.precedence() <= prec)
+            },
+            Node::Logic(op, l, r) => {
+                child(f, l, l.precedence() < prec)?;
+                f.write_str(if *op == LogicOp::And { " && " } else { " || " })?;
+                child(f, r, r.precedence() <= prec)
+            },
+            Node::Compare(op, l, r) => {
+                let op = match op {
+                    CmpOp::Lt => "<",
+                    CmpOp::Le => "<=",
+                    CmpOp::Gt => ">",
+                    CmpOp::Ge => ">=",
+                    CmpOp::Eq => "==",
+                    CmpOp::Ne => "!=",
+                };
+                child(f, l, l.precedence() <= prec)?;
+                write!(f, " {} ", op)?;
+                child(f, r, r.precedence() <= prec)
+            },
+            Node::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, a) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", a)?;
+                }
+                f.write_str(")")
+            },
+        }
+    }
+}
+
+/// Tokens which can start an operand
+const OPERAND: &[&str] = &["identifier", "number", "`(`", "`-`", "`+`", "`!`"];
+
+/// Whether the node can stand where a condition is expected, variables can be both
+fn condition(node: &Node, span: Span) -> Result<(), SyntaxError> {
+    match node {
+        Node::Var(_) | Node::Not(_) | Node::Logic(..) | Node::Compare(..) => Ok(()),
+        _ => Err(SyntaxError::new(ErrorKind::ExpectedCondition, span)),
+    }
+}
+
+/// Whether the node can stand where a number is expected
+fn number(node: &Node, span: Span) -> Result<(), SyntaxError> {
+    match node {
+        Node::Var(_) | Node::Const(_) | Node::Unary(..) | Node::Binary(..) | Node::Call(..) => Ok(()),
+        _ => Err(SyntaxError::new(ErrorKind::ExpectedNumber, span)),
+    }
+}
+
+/// Precedence climbing parser over a token slice, operands are checked to be
+/// conditions or numbers as the operators require
+pub struct Parser<'a> {
+    tokens: &'a [Spanned],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Spanned]) -> Parser<'a> {
+        Parser { tokens, pos: 0 }
+    }
+
+    /// Whole slice as a single expression of any kind
+    pub fn parse(tokens: &'a [Spanned]) -> Result<Node, SyntaxError> {
+        let mut p = Parser::new(tokens);
+        let (node, _) = p.expr(1)?;
+        p.finish(&["operator"])?;
+        Ok(node)
+    }
+
+    /// Condition starting at the current token, stops at the first token which can not continue it
+    pub fn condition(&mut self) -> Result<Node, SyntaxError> {
+        let (node, span) = self.expr(1)?;
+        condition(&node, span)?;
+        Ok(node)
+    }
+
+    /// Numeric expression starting at the current token
+    pub fn formula(&mut self) -> Result<Node, SyntaxError> {
+        let (node, span) = self.expr(1)?;
+        number(&node, span)?;
+        Ok(node)
+    }
+
+    /// Consumes `t` or fails with what was found instead
+    pub fn expect(&mut self, t: &Token) -> Result<Span, SyntaxError> {
+        match self.eat(t) {
+            Some(span) => Ok(span),
+            None => Err(self.unexpected(&[&t.to_string()])),
+        }
+    }
+
+    /// Consumes an identifier, `what` names it in the error
+    pub fn ident(&mut self, what: &str) -> Result<String, SyntaxError> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                Ok(name.clone())
+            },
+            _ => Err(self.unexpected(&[what])),
+        }
+    }
+
+    /// Fails unless every token is consumed
+    pub fn finish(&self, expected: &[&str]) -> Result<(), SyntaxError> {
+        match self.peek() {
+            Some(_) => Err(self.unexpected(expected)),
+            None => Ok(()),
+        }
+    }
+
+    /// Error at the current token, or at the end of input when there is none
+    pub fn unexpected(&self, expected: &[&str]) -> SyntaxError {
+        match self.tokens.get(self.pos) {
+            Some(t) => SyntaxError::new(ErrorKind::UnexpectedToken, t.span).found(t.token.to_string()),
+            None => {
+                let end = self.tokens.last().map(|t| t.span.end).unwrap_or(0);
+                SyntaxError::new(ErrorKind::UnexpectedEnd, Span::new(end, end))
+            },
+        }.expected(expected)
+    }
+
+    /// Skips the tokens up to and including the next `t`, used to resume after an error.
+    /// Returns false when the input ends first
+    pub fn skip_past(&mut self, t: &Token) -> bool {
+        while let Some(cur) = self.peek() {
+            self.pos += 1;
+            if cur == t {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    /// Consumes the current token if it is `t`
+    fn eat(&mut self, t: &Token) -> Option<Span> {
+        match self.tokens.get(self.pos) {
+            Some(s) if s.token == *t => {
+                self.pos += 1;
+                Some(s.span)
+            },
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, min_prec: u8) -> Result<(Node, Span), SyntaxError> {
+        let (mut lhs, mut span) = self.unary()?;
+        while let Some(op) = self.peek().and_then(Infix::from_token) {
+            if op.precedence() < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let (rhs, rhs_span) = self.expr(op.precedence() + 1)?;
+            lhs = match op {
+                Infix::Bin(op) => {
+                    number(&lhs, span)?;
+                    number(&rhs, rhs_span)?;
+                    Node::Binary(op, Box::new(lhs), Box::new(rhs))
+                },
+                Infix::Logic(op) => {
+                    condition(&lhs, span)?;
+                    condition(&rhs, rhs_span)?;
+                    Node::Logic(op, Box::new(lhs), Box::new(rhs))
+                },
+                Infix::Cmp(op) => {
+                    if let Some(Infix::Cmp(_)) = self.peek().and_then(Infix::from_token) {
+                        return Err(SyntaxError::new(ErrorKind::ChainedComparison, self.tokens[self.pos].span));
+                    }
+                    number(&lhs, span)?;
+                    number(&rhs, rhs_span)?;
+                    Node::Compare(op, Box::new(lhs), Box::new(rhs))
+                },
+            };
+            span = span.to(rhs_span);
+        }
+        Ok((lhs, span))
+    }
+
+    /// Prefix `-`, `+` and `!` bind tighter than any binary operator
+    fn unary(&mut self) -> Result<(Node, Span), SyntaxError> {
+        let start = match self.tokens.get(self.pos) {
+            Some(t) => t.span,
+            None => return self.primary(),
+        };
+        let op = match self.peek() {
+            Some(Token::Minus) => UnOp::Neg,
+            Some(Token::Plus) => UnOp::Pos,
+            Some(Token::Not) => {
+                self.pos += 1;
+                let (n, span) = self.unary()?;
+                condition(&n, span)?;
+                return Ok((Node::Not(Box::new(n)), start.to(span)));
+            },
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        let (n, span) = self.unary()?;
+        number(&n, span)?;
+        Ok((Node::Unary(op, Box::new(n)), start.to(span)))
+    }
+
+    fn primary(&mut self) -> Result<(Node, Span), SyntaxError> {
+        let t = match self.tokens.get(self.pos) {
+            Some(t) => t,
+            None => return Err(self.unexpected(OPERAND)),
+        };
+        let call = self.tokens.get(self.pos + 1).map(|t| &t.token) == Some(&Token::Open);
+        match &t.token {
+            Token::Ident(name) if call => {
+                self.pos += 2;
+                let (args, close) = self.args()?;
+                Ok((Node::Call(name.clone(), args), t.span.to(close)))
+            },
+            Token::Ident(name) => {
+                self.pos += 1;
+                Ok((Node::Var(name.clone()), t.span))
+            },
+            Token::Const(v) => {
+                self.pos += 1;
+                Ok((Node::Const(*v), t.span))
+            },
+            Token::Open => {
+                self.pos += 1;
+                let (node, _) = self.expr(1)?;
+                match self.eat(&Token::Close) {
+                    Some(close) => Ok((node, t.span.to(close))),
+                    None => Err(self.unexpected(&["operator", "`)`"])),
+                }
+            },
+            _ => Err(self.unexpected(OPERAND)),
+        }
+    }
+
+    /// Comma separated call arguments, the opening parenthesis is already consumed,
+    /// returns them with the span of the closing one
+    fn args(&mut self) -> Result<(Vec<Node>, Span), SyntaxError> {
+        let mut args = vec![];
+        if let Some(close) = self.eat(&Token::Close) {
+            return Ok((args, close));
+        }
+        loop {
+            let (arg, span) = self.expr(1)?;
+            number(&arg, span)?;
+            args.push(arg);
+            if self.eat(&Token::Comma).is_some() {
+                continue;
+            }
+            match self.eat(&Token::Close) {
+                Some(close) => return Ok((args, close)),
+                None => return Err(self.unexpected(&["operator", "`,`", "`)`"])),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Parser, Node, BinOp, UnOp, LogicOp, CmpOp};
+    use crate::lib::tokenize::{tokenize, Token};
+
+    fn var(name: &str) -> Node {
+        Node::Var(name.to_string())
+    }
+
+    fn bin(op: BinOp, l: Node, r: Node) -> Node {
+        Node::Binary(op, Box::new(l), Box::new(r))
+    }
+
+    #[test]
+    fn test_parse() {
+
+        let n = Parser::parse(&tokenize("D - E - F").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Sub, bin(BinOp::Sub, var("D"), var("E")), var("F")), n);
+
+        let n = Parser::parse(&tokenize("D + E * F").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Add, var("D"), bin(BinOp::Mul, var("E"), var("F"))), n);
+
+        let n = Parser::parse(&tokenize("(D + E) / 2").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Div, bin(BinOp::Add, var("D"), var("E")), Node::Const(2.0)), n);
+
+        let n = Parser::parse(&tokenize("order_total * items_count").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Mul, var("order_total"), var("items_count")), n);
+    }
+
+    #[test]
+    fn test_parse_unary() {
+        let neg = |n: Node| Node::Unary(UnOp::Neg, Box::new(n));
+
+        let n = Parser::parse(&tokenize("-D + 5").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Add, neg(var("D")), Node::Const(5.0)), n);
+
+        let n = Parser::parse(&tokenize("D * -1.5").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Mul, var("D"), neg(Node::Const(1.5))), n);
+
+        let n = Parser::parse(&tokenize("D - -E").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Sub, var("D"), neg(var("E"))), n);
+
+        let n = Parser::parse(&tokenize("-(D - +E)").unwrap()).unwrap();
+        assert_eq!(neg(bin(BinOp::Sub, var("D"), Node::Unary(UnOp::Pos, Box::new(var("E"))))), n);
+    }
+
+    #[test]
+    fn test_parse_conditions() {
+        let logic = |op: LogicOp, l: Node, r: Node| Node::Logic(op, Box::new(l), Box::new(r));
+        let cmp = |op: CmpOp, l: Node, r: Node| Node::Compare(op, Box::new(l), Box::new(r));
+        let not = |n: Node| Node::Not(Box::new(n));
+
+        let n = Parser::parse(&tokenize("A || B && !C").unwrap()).unwrap();
+        assert_eq!(logic(LogicOp::Or, var("A"), logic(LogicOp::And, var("B"), not(var("C")))), n);
+
+        let n = Parser::parse(&tokenize("A && D > 100 && E <= 3").unwrap()).unwrap();
+        assert_eq!(logic(LogicOp::And,
+                         logic(LogicOp::And, var("A"), cmp(CmpOp::Gt, var("D"), Node::Const(100.0))),
+                         cmp(CmpOp::Le, var("E"), Node::Const(3.0))), n);
+
+        let n = Parser::parse(&tokenize("(D + 1) * 2 != E - 1 || !(D == E)").unwrap()).unwrap();
+        assert_eq!(logic(LogicOp::Or,
+                         cmp(CmpOp::Ne,
+                             bin(BinOp::Mul, bin(BinOp::Add, var("D"), Node::Const(1.0)), Node::Const(2.0)),
+                             bin(BinOp::Sub, var("E"), Node::Const(1.0))),
+                         not(cmp(CmpOp::Eq, var("D"), var("E")))), n);
+
+        assert!(Parser::parse(&tokenize("D < E < F").unwrap()).is_err());
+        assert!(Parser::parse(&tokenize("D < ").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_parse_calls() {
+        let n = Parser::parse(&tokenize("round(D * 1.2, 2)").unwrap()).unwrap();
+        assert_eq!(Node::Call("round".to_string(), vec![
+            bin(BinOp::Mul, var("D"), Node::Const(1.2)), Node::Const(2.0)]), n);
+
+        let n = Parser::parse(&tokenize("max(0, D - F) + now()").unwrap()).unwrap();
+        assert_eq!(bin(BinOp::Add,
+                       Node::Call("max".to_string(), vec![Node::Const(0.0), bin(BinOp::Sub, var("D"), var("F"))]),
+                       Node::Call("now".to_string(), vec![])), n);
+
+        for s in ["max(D", "max(D,", "max(D E)", "max(,)", "max D)"] {
+            assert!(Parser::parse(&tokenize(s).unwrap()).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let values = vec![
+            ("D + (D * (E - F) / 25.5)", "D + D * (E - F) / 25.5"),
+            ("D - (E - F)", "D - (E - F)"),
+            ("(D - E) - F", "D - E - F"),
+            ("-(D + 1) * -2", "-(D + 1) * -2"),
+            ("(A || B) && !(C && D > 1)", "(A || B) && !(C && D > 1)"),
+            ("A || (B && C)", "A || B && C"),
+            ("(D + 1) * 2 > -(E - 3)", "(D + 1) * 2 > -(E - 3)"),
+            ("round(max(D, 0) * 1.2, 2)", "round(max(D, 0) * 1.2, 2)"),
+        ];
+        for (src, expected) in values {
+            let n = Parser::parse(&tokenize(src).unwrap()).unwrap();
+            assert_eq!(expected, n.to_string());
+            assert_eq!(n, Parser::parse(&tokenize(&n.to_string()).unwrap()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        for s in vec!["D +", "(D", "D)", "D E", "()", "* D", "", "-", "D * -"] {
+            assert!(Parser::parse(&tokenize(s).unwrap()).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let values = vec![
+            ("D +", "unexpected end of input, expected identifier, number, `(`, `-`, `+` or `!`", (3, 3)),
+            ("(D * 2", "unexpected end of input, expected operator or `)`", (6, 6)),
+            ("D E", "unexpected identifier `E`, expected operator", (2, 3)),
+            ("max(D E)", "unexpected identifier `E`, expected operator, `,` or `)`", (6, 7)),
+            ("A && )", "unexpected `)`, expected identifier, number, `(`, `-`, `+` or `!`", (5, 6)),
+            ("D < E <= F", "comparisons can not be chained", (6, 8)),
+            ("A && (D + 1) * 2", "expected condition, found numeric expression", (5, 16)),
+            ("D > (A || B)", "expected numeric expression, found condition", (4, 12)),
+            ("!max(D, 1)", "expected condition, found numeric expression", (1, 10)),
+            ("-(D > 1)", "expected numeric expression, found condition", (1, 8)),
+            ("round(A && B)", "expected numeric expression, found condition", (6, 12)),
+        ];
+        for (src, message, span) in values {
+            let e = Parser::parse(&tokenize(src).unwrap()).unwrap_err();
+            assert_eq!((message, span), (e.to_string().as_str(), (e.span.start, e.span.end)), "{}", src);
+        }
+
+        let tokens = tokenize("D + 1 => H = M").unwrap();
+        let mut p = Parser::new(&tokens);
+        assert_eq!("expected condition, found numeric expression", p.condition().unwrap_err().to_string());
+        let mut p = Parser::new(&tokens);
+        assert!(p.formula().is_ok());
+        assert!(p.expect(&Token::Arrow).is_ok());
+        assert_eq!("unexpected `H`, expected category name", p.ident("category name").unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use std::fmt;
+
+/// Instruction of a reverse polish program, the `Display` form names it in errors
+pub(crate) trait Instruction: fmt::Display {
+    /// Number of values the instruction pops and pushes
+    fn effect(&self) -> (usize, usize);
+}
+
+/// Stack depth analysis: no instruction pops more than the stack holds and exactly
+/// one value is left at the end
+pub(crate) fn verify<I: Instruction>(program: &[I]) -> Result<()> {
+    let mut depth = 0;
+    for (pos, i) in program.iter().enumerate() {
+        let (pops, pushes) = i.effect();
+        if depth < pops {
+            return Err(anyhow!("invalid program: instruction {} ({}) needs {} values, found {}", pos, i, pops, depth));
+        }
+        depth = depth - pops + pushes;
+    }
+    if depth != 1 {
+        return Err(anyhow!("invalid program: leaves {} values, expected 1", depth));
+    }
+    Ok(())
+}
+
+/// Programs are verified when built, the errors of `pop` and `result` only guard
+/// against a broken invariant
+pub(crate) fn pop<T>(stack: &mut Vec<T>) -> Result<T> {
+    stack.pop().ok_or_else(|| anyhow!("stack underflow"))
+}
+
+/// The single value a program left on the stack
+pub(crate) fn result<T>(mut stack: Vec<T>) -> Result<T> {
+    let v = pop(&mut stack)?;
+    if !stack.is_empty() {
+        return Err(anyhow!("{} values left on the stack", stack.len()));
+    }
+    Ok(v)
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use crate::lib::expr::Expr;
+use crate::lib::app::Strategy;
+
+/// Expression stored in the `App` together with its metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    id: u64,
+    expr: Expr,
+    source: String,
+    priority: i64,
+    name: Option<String>,
+    owner: Option<String>,
+    description: Option<String>,
+}
+
+impl Rule {
+    /// The source is the canonical text of the expression until `with_source` sets the original one
+    pub fn new(expr: Expr) -> Rule {
+        Rule {
+            id: 0,
+            source: expr.to_string(),
+            expr,
+            priority: 0,
+            name: None,
+            owner: None,
+            description: None,
+        }
+    }
+
+    /// Ids are given by the `App` the rule is added to
+    pub(crate) fn with_id(mut self, id: u64) -> Rule {
+        self.id = id;
+        self
+    }
+
+    pub fn with_source(mut self, source: &str) -> Rule {
+        self.source = source.trim().to_string();
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i64) -> Rule {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_name(mut self, name: Option<String>) -> Rule {
+        self.name = name;
+        self
+    }
+
+    pub fn with_owner(mut self, owner: Option<String>) -> Rule {
+        self.owner = owner;
+        self
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Rule {
+        self.description = description;
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// Text the rule was added from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn priority(&self) -> i64 {
+        self.priority
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn to_source(&self) -> RuleSource {
+        RuleSource {
+            id: Some(self.id),
+            name: self.name.clone(),
+            owner: self.owner.clone(),
+            description: self.description.clone(),
+            source: self.source.clone(),
+            priority: self.priority,
+        }
+    }
+}
+
+/// Serialized form of a rule, see `RuleSet`. A rule without id gets the next free one
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub source: String,
+    #[serde(default)]
+    pub priority: i64,
+}
+
+/// Everything needed to rebuild an `App` except the custom functions, which are
+/// registered from Rust on the `App` the set is loaded into
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub categories: Vec<String>,
+    #[serde(default = "first_match")]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub rules: Vec<RuleSource>,
+    /// Id the next added rule gets, ids of deleted rules are not given again
+    #[serde(default)]
+    pub next_id: u64,
+}
+
+pub(crate) fn first_match() -> Strategy {
+    Strategy::FirstMatch
+}
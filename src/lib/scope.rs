@@ -1,35 +1,119 @@
-#[derive(Debug, Copy, Clone, Default)]
+use anyhow::Result;
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Value {
+        Value::Bool(v)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Value {
+        Value::Int(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Value {
+        Value::Float(v)
+    }
+}
+
+/// Input variables of a single evaluation, looked up by name
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
 pub struct Scope {
-    pub a: bool,
-    pub b: bool,
-    pub c: bool,
-    pub d: f64,
-    pub e: i64,
-    pub f: i64,
+    vars: HashMap<String, Value>,
 }
 
 impl Scope {
+    /// Scope with the classic `A`, `B`, `C` (bool), `D` (float), `E`, `F` (int) inputs
     pub fn new(a: bool, b: bool, c: bool, d: f64, e: i64, f: i64) -> Scope {
-        Scope {
-            a, b, c, d, e, f
-        }
+        Scope::abc(a, b, c)
+            .set("D", d)
+            .set("E", e)
+            .set("F", f)
     }
 
     pub fn abc(a: bool, b: bool, c: bool) -> Scope {
-        return Scope {
-            a,
-            b,
-            c,
-            ..Default::default()
-        }
+        Scope::default()
+            .set("A", a)
+            .set("B", b)
+            .set("C", c)
     }
 
     pub fn def(d: f64, e: i64, f: i64) -> Scope {
-        return Scope {
-            d,
-            e,
-            f,
-            ..Default::default()
+        Scope::default()
+            .set("D", d)
+            .set("E", e)
+            .set("F", f)
+    }
+
+    pub fn set<V: Into<Value>>(mut self, name: &str, value: V) -> Scope {
+        self.vars.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.vars.get(name).copied()
+    }
+
+    pub fn bool(&self, name: &str) -> Result<bool> {
+        match self.get(name) {
+            Some(Value::Bool(v)) => Ok(v),
+            Some(v) => Err(anyhow!("variable `{}` is {}, expected bool", name, v.type_name())),
+            None => Err(anyhow!("unknown variable `{}`", name)),
+        }
+    }
+
+    /// Numeric value of the variable, ints are widened to float
+    pub fn number(&self, name: &str) -> Result<f64> {
+        match self.get(name) {
+            Some(Value::Int(v)) => Ok(v as f64),
+            Some(Value::Float(v)) => Ok(v),
+            Some(v) => Err(anyhow!("variable `{}` is {}, expected number", name, v.type_name())),
+            None => Err(anyhow!("unknown variable `{}`", name)),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Scope, Value};
+
+    #[test]
+    fn test_lookup() {
+        let s = Scope::default()
+            .set("is_vip", true)
+            .set("items_count", 3)
+            .set("order_total", 99.5);
+
+        assert_eq!(Some(Value::Int(3)), s.get("items_count"));
+        assert!(s.bool("is_vip").unwrap());
+        assert_eq!(3.0, s.number("items_count").unwrap());
+        assert_eq!(99.5, s.number("order_total").unwrap());
+
+        assert_eq!("unknown variable `vip`", s.bool("vip").unwrap_err().to_string());
+        assert_eq!("variable `order_total` is float, expected bool", s.bool("order_total").unwrap_err().to_string());
+        assert_eq!("variable `is_vip` is bool, expected number", s.number("is_vip").unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,239 @@
+use crate::lib::app::Strategy;
+use crate::lib::error::{position, ErrorKind, RuleError, SyntaxError, SyntaxErrors};
+use crate::lib::rule::{RuleSet, RuleSource};
+use crate::lib::tokenize::{tokenize_all, Span, Spanned, Token};
+
+/// Rule set of a `.sprout` file with the byte offset of every rule source in the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SproutFile {
+    pub set: RuleSet,
+    pub offsets: Vec<usize>,
+}
+
+impl SproutFile {
+    /// Renders the errors of the rules at their place in the file, `origin` is put before line and column
+    pub fn render(&self, text: &str, errors: &[RuleError], origin: &str) -> String {
+        let offset = |e: &RuleError| self.offsets.get(e.rule).cloned().unwrap_or(0);
+        let all: Vec<String> = errors.iter().map(|e| match &e.syntax {
+            Some(se) => {
+                let mut se = se.clone();
+                se.span = Span::new(se.span.start + offset(e), se.span.end + offset(e));
+                se.render_at(text, origin)
+            },
+            None => {
+                let (line, col) = position(text, offset(e));
+                format!("error: {}\n --> {}{}:{}", e.message, origin, line, col)
+            },
+        }).collect();
+        all.join("\n")
+    }
+}
+
+/// Splits a `.sprout` file into categories and rules, the rules themselves are built by `App`.
+/// A statement starts at the beginning of a line and goes on over the indented lines after it:
+/// `categories M, P` declares categories, anything else is a rule. `rule "name" { ... }` wraps
+/// one rule, `priority 5` (or `-5`), `owner "..."` and `description "..."` lines before it set
+/// its metadata. `#` comments and blank lines are ignored
+pub fn parse(text: &str) -> Result<SproutFile, SyntaxErrors> {
+    let (tokens, errors) = tokenize_all(text);
+    if !errors.is_empty() {
+        return Err(SyntaxErrors(errors));
+    }
+    let mut p = FileParser {
+        text,
+        tokens: &tokens,
+        pos: 0,
+        file: SproutFile {
+            set: RuleSet {
+                categories: vec![],
+                strategy: Strategy::FirstMatch,
+                rules: vec![],
+                next_id: 0,
+            },
+            offsets: vec![],
+        },
+        errors: vec![],
+    };
+    while p.pos < tokens.len() {
+        p.statement();
+    }
+    if p.errors.is_empty() {
+        return Ok(p.file);
+    }
+    Err(SyntaxErrors(p.errors))
+}
+
+struct FileParser<'a> {
+    text: &'a str,
+    tokens: &'a [Spanned],
+    pos: usize,
+    file: SproutFile,
+    errors: Vec<SyntaxError>,
+}
+
+impl<'a> FileParser<'a> {
+    fn statement(&mut self) {
+        let end = self.statement_end();
+        match (&self.tokens[self.pos].token, self.tokens.get(self.pos + 1).map(|t| &t.token)) {
+            (Token::Ident(kw), Some(Token::Ident(_))) if kw == "categories" => self.categories(end),
+            (Token::Ident(kw), Some(Token::Str(_))) if kw == "rule" => self.block(),
+            _ => self.rule(RuleSource::default(), end),
+        }
+    }
+
+    /// Index of the next token which starts a line
+    fn statement_end(&self) -> usize {
+        (self.pos + 1..self.tokens.len())
+            .find(|i| {
+                let start = self.tokens[*i].span.start;
+                self.text[..start].ends_with('\n')
+            })
+            .unwrap_or(self.tokens.len())
+    }
+
+    fn categories(&mut self, end: usize) {
+        for t in &self.tokens[self.pos + 1..end] {
+            match &t.token {
+                Token::Ident(name) => self.file.set.categories.push(name.clone()),
+                Token::Comma => {},
+                other => self.errors.push(SyntaxError::new(ErrorKind::UnexpectedToken, t.span)
+                    .found(other.to_string())
+                    .expected(&["category name"])),
+            }
+        }
+        self.pos = end;
+    }
+
+    /// `rule "name" { <metadata> <rule> }`, the braces may span any lines
+    fn block(&mut self) {
+        let name = match &self.tokens[self.pos + 1].token {
+            Token::Str(name) => Some(name.clone()),
+            _ => None,
+        };
+        self.pos += 2;
+        if self.tokens.get(self.pos).map(|t| &t.token) != Some(&Token::OpenBrace) {
+            self.errors.push(self.unexpected(self.pos, &["`{`"]));
+            self.pos = self.statement_end();
+            return;
+        }
+        self.pos += 1;
+        let close = match (self.pos..self.tokens.len()).find(|i| self.tokens[*i].token == Token::CloseBrace) {
+            Some(close) => close,
+            None => {
+                self.pos = self.tokens.len();
+                self.errors.push(self.unexpected(self.pos, &["`}`"]));
+                return;
+            },
+        };
+        let mut rule = RuleSource {
+            name,
+            ..RuleSource::default()
+        };
+        while let (Some(Token::Ident(key)), Some(value)) = (self.token(self.pos), self.token(self.pos + 1)) {
+            // the sign of `priority -1` is a token of its own
+            let negative = key == "priority" && *value == Token::Minus;
+            let at = if negative { self.pos + 2 } else { self.pos + 1 };
+            match (key.as_str(), self.token(at)) {
+                ("priority", Some(Token::Const(v))) if v.fract() == 0.0 => {
+                    rule.priority = if negative { -*v as i64 } else { *v as i64 }
+                },
+                ("owner", Some(Token::Str(s))) => rule.owner = Some(s.clone()),
+                ("description", Some(Token::Str(s))) => rule.description = Some(s.clone()),
+                ("priority", Some(Token::Const(_))) | ("priority", Some(Token::Str(_))) => {
+                    self.errors.push(self.unexpected(at, &["integer"]))
+                },
+                ("priority", _) if negative => self.errors.push(self.unexpected(at, &["integer"])),
+                ("owner", Some(Token::Const(_))) | ("description", Some(Token::Const(_))) => {
+                    self.errors.push(self.unexpected(at, &["string"]))
+                },
+                _ => break,
+            }
+            self.pos = at + 1;
+        }
+        if self.pos >= close {
+            self.errors.push(self.unexpected(close, &["rule"]));
+        } else {
+            self.rule(rule, close);
+        }
+        self.pos = close + 1;
+    }
+
+    /// Rule made of the tokens up to `end`, its source is the text they span
+    fn rule(&mut self, mut rule: RuleSource, end: usize) {
+        let start = self.tokens[self.pos].span.start;
+        rule.source = self.text[start..self.tokens[end - 1].span.end].to_string();
+        self.file.set.rules.push(rule);
+        self.file.offsets.push(start);
+        self.pos = end;
+    }
+
+    fn token(&self, pos: usize) -> Option<&'a Token> {
+        self.tokens.get(pos).map(|t| &t.token)
+    }
+
+    /// Error at the token at `pos`, or at the end of the file when there is none
+    fn unexpected(&self, pos: usize, expected: &[&str]) -> SyntaxError {
+        match self.tokens.get(pos) {
+            Some(t) => SyntaxError::new(ErrorKind::UnexpectedToken, t.span).found(t.token.to_string()),
+            None => SyntaxError::new(ErrorKind::UnexpectedEnd, Span::new(self.text.len(), self.text.len())),
+        }.expected(expected)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    const RULES: &str = "# default pricing
+categories M, P
+categories GOLD
+
+A && B && !C => H = M
+A && B
+    && C    # all of them
+    => H = P
+
+rule \"vip\" {
+    priority 10
+    owner \"pricing\"
+    description \"vip customers\"
+    is_vip
+        => H = GOLD
+}
+
+H = M => K = D + (D * E / 10)
+";
+
+    #[test]
+    fn test_parse() {
+        let file = parse(RULES).unwrap();
+        assert_eq!(vec!["M", "P", "GOLD"], file.set.categories);
+        let sources: Vec<&str> = file.set.rules.iter().map(|r| r.source.as_str()).collect();
+        assert_eq!(vec![
+            "A && B && !C => H = M",
+            "A && B\n    && C    # all of them\n    => H = P",
+            "is_vip\n        => H = GOLD",
+            "H = M => K = D + (D * E / 10)",
+        ], sources);
+        let vip = &file.set.rules[2];
+        assert_eq!((Some("vip"), Some("pricing"), Some("vip customers"), 10),
+                   (vip.name.as_deref(), vip.owner.as_deref(), vip.description.as_deref(), vip.priority));
+        assert_eq!(RULES.find("A && B\n").unwrap(), file.offsets[1]);
+
+        let file = parse("categories M\nrule \"late\" {\n  priority -1\n  A => H = M\n}").unwrap();
+        assert_eq!((-1, "A => H = M"), (file.set.rules[0].priority, file.set.rules[0].source.as_str()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |s: &str| parse(s).unwrap_err().to_string();
+        assert_eq!("unterminated string, expected `\"`", err("rule \"vip {\n  A => H = M\n}"));
+        assert_eq!("unexpected `=>`, expected `{`", err("rule \"vip\" => H = M"));
+        assert_eq!("unexpected end of input, expected `}`", err("rule \"vip\" {\n  A => H = M\n"));
+        assert_eq!("unexpected string \"high\", expected integer\nunexpected `}`, expected rule",
+                   err("rule \"vip\" {\n  priority \"high\"\n}"));
+        assert_eq!("unexpected number `1.5`, expected integer\nunexpected `}`, expected rule",
+                   err("rule \"vip\" {\n  priority -1.5\n}"));
+        assert_eq!("unexpected number `1`, expected category name", err("categories M, 1"));
+    }
+}
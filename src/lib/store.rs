@@ -0,0 +1,341 @@
+use anyhow::Result;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use crate::lib::app::App;
+use crate::lib::error::render_all;
+use crate::lib::history::Version;
+use crate::lib::rule::RuleSet;
+
+/// Versions a store keeps unless `Store::with_retention` says otherwise
+const KEEP_VERSIONS: usize = 100;
+
+/// Shared rule set of the service. Readers get the current `App` and keep it for the
+/// whole request, changes build a new `App`, write it to the file and only then add it
+/// as the next version. Versions are kept in memory up to the retention, the oldest go
+/// first and numbering starts again on restart. Clones share the rules
+#[derive(Clone)]
+pub struct Store {
+    path: Option<PathBuf>,
+    versions: Arc<RwLock<Vec<Arc<Version>>>>,
+    keep: usize,
+}
+
+impl Store {
+    /// Store without a file, changes last until the process exits
+    pub fn in_memory(app: App) -> Store {
+        Store::with_first(None, "initial rules", app)
+    }
+
+    /// Loads the rule set of the json file into `base`, which provides the functions.
+    /// A missing file is created from `base` itself
+    pub fn open<P: AsRef<Path>>(path: P, base: App) -> Result<Store> {
+        let path = path.as_ref().to_path_buf();
+        let (change, app) = if path.exists() {
+            (format!("loaded {}", path.display()), load(&path, &base)?)
+        } else {
+            save(&path, &base)?;
+            (format!("created {}", path.display()), base)
+        };
+        Ok(Store::with_first(Some(path), &change, app))
+    }
+
+    fn with_first(path: Option<PathBuf>, change: &str, app: App) -> Store {
+        Store {
+            path,
+            versions: Arc::new(RwLock::new(vec![Arc::new(Version::new(1, change, Arc::new(app)))])),
+            keep: KEEP_VERSIONS,
+        }
+    }
+
+    /// Keeps at most `versions` versions, at least the current one
+    pub fn with_retention(self, versions: usize) -> Store {
+        Store {
+            keep: versions.max(1),
+            ..self
+        }
+    }
+
+    pub fn app(&self) -> Arc<App> {
+        self.current().app.clone()
+    }
+
+    pub fn current(&self) -> Arc<Version> {
+        let versions = self.versions.read().unwrap_or_else(|e| e.into_inner());
+        versions[versions.len() - 1].clone()
+    }
+
+    pub fn versions(&self) -> Vec<Arc<Version>> {
+        self.versions.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn version(&self, number: u64) -> Option<Arc<Version>> {
+        self.versions().into_iter().find(|v| v.number == number)
+    }
+
+    /// Applies `f` to the current `App`, persists the result and adds it as a new version
+    /// described by `change`. Changes are applied one at a time, when `f`, `App::validate`
+    /// or the write fails nothing changes
+    pub fn update<F>(&self, change: &str, f: F) -> Result<Arc<Version>>
+        where F: FnOnce(&App) -> Result<App> {
+        let mut versions = self.versions.write().unwrap_or_else(|e| e.into_inner());
+        let app = f(&versions[versions.len() - 1].app)?;
+        app.validate()?;
+        self.push(&mut versions, change, Arc::new(app))
+    }
+
+    /// Makes the rules of an earlier version current again as a new version
+    pub fn rollback(&self, number: u64) -> Result<Arc<Version>> {
+        let mut versions = self.versions.write().unwrap_or_else(|e| e.into_inner());
+        let app = versions.iter().find(|v| v.number == number)
+            .map(|v| v.app.clone())
+            .ok_or_else(|| anyhow!("version {} not found", number))?;
+        self.push(&mut versions, &format!("rollback to {}", number), app)
+    }
+
+    /// Loads the file again with the functions of the current `App`. The new rules become
+    /// a version only if the whole file builds and differs from the current rules, which
+    /// is not the case after the store wrote the file itself. The file is read under the
+    /// write lock so a concurrent change is either in it or applied after it
+    pub fn reload(&self) -> Result<Option<Arc<Version>>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut versions = self.versions.write().unwrap_or_else(|e| e.into_inner());
+        let app = load(path, &versions[versions.len() - 1].app)?;
+        if app == *versions[versions.len() - 1].app {
+            return Ok(None);
+        }
+        Ok(Some(self.append(&mut versions, &format!("reloaded {}", path.display()), Arc::new(app))))
+    }
+
+    fn push(&self, versions: &mut Vec<Arc<Version>>, change: &str, app: Arc<App>) -> Result<Arc<Version>> {
+        if let Some(path) = &self.path {
+            save(path, &app)?;
+        }
+        Ok(self.append(versions, change, app))
+    }
+
+    /// Adds the next version and drops the oldest beyond the retention
+    fn append(&self, versions: &mut Vec<Arc<Version>>, change: &str, app: Arc<App>) -> Arc<Version> {
+        let number = versions[versions.len() - 1].number + 1;
+        let version = Arc::new(Version::new(number, change, app));
+        versions.push(version.clone());
+        if versions.len() > self.keep {
+            let old = versions.len() - self.keep;
+            versions.drain(..old);
+        }
+        version
+    }
+}
+
+/// Notices changes of the rule file by its modification time and size
+pub struct Watcher {
+    store: Store,
+    seen: Option<(SystemTime, u64)>,
+}
+
+impl Watcher {
+    pub fn new(store: Store) -> Watcher {
+        let seen = stamp(store.path.as_deref());
+        Watcher {
+            store,
+            seen,
+        }
+    }
+
+    /// Reloads the store when the file changed since the last call, see `Store::reload`
+    pub fn poll(&mut self) -> Result<Option<Arc<Version>>> {
+        let now = stamp(self.store.path.as_deref());
+        if now == self.seen {
+            return Ok(None);
+        }
+        self.seen = now;
+        self.store.reload()
+    }
+}
+
+fn stamp(path: Option<&Path>) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path?).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+/// Rule set of the file loaded into `base`, errors point at the rule in the file
+pub fn load(path: &Path, base: &App) -> Result<App> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow!("can not read {}: {}", path.display(), e))?;
+    let set: RuleSet = serde_json::from_str(&text)
+        .map_err(|e| anyhow!("invalid rule file {}: {}", path.display(), e))?;
+    base.with_rule_set(&set).map_err(|errors| {
+        let sources: Vec<&str> = set.rules.iter().map(|r| r.source.as_str()).collect();
+        anyhow!(render_all(&errors, &sources, &format!("{}:rules", path.display())))
+    })
+}
+
+/// Writes the rule set next to the file and renames it over, a crash leaves either
+/// the old or the new file. The directory is synced too so the rename itself is durable
+/// The rule file could not be written, the change was not applied
+#[derive(Debug)]
+pub struct SaveError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can not write {}: {}", self.path.display(), self.error)
+    }
+}
+
+impl Error for SaveError {}
+
+pub fn save(path: &Path, app: &App) -> Result<()> {
+    let text = serde_json::to_string_pretty(&app.rule_set())?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let write = || -> io::Result<()> {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        fs::File::open(dir)?.sync_all()
+    };
+    write().map_err(|error| SaveError { path: path.to_path_buf(), error }.into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::lib::app::App;
+    use crate::lib::scope::Scope;
+    use super::{SaveError, Store, Watcher};
+
+    fn temp(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sprout-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_persist() {
+        let path = temp("persist");
+        let store = Store::open(&path, App::default()).unwrap();
+        assert!(path.exists());
+
+        store.update("add", |app| app.add("!A && !B && !C => H = P")).unwrap();
+        let before = store.app();
+        store.update("remove", |app| app.remove("A && B && C => H = P")).unwrap();
+        assert_eq!(7, before.rule_set().rules.len());
+
+        let reopened = Store::open(&path, App::new()).unwrap();
+        assert_eq!(store.app().rule_set(), reopened.app().rule_set());
+        assert_eq!("!A && !B && !C => H = P", reopened.app().rule_set().rules[5].source);
+        assert_eq!(3.0, reopened.app().run(&Scope::new(false, false, false, 1.0, 52, 1)).unwrap());
+
+        assert!(store.update("add", |app| app.add("A => H = GOLD")).is_err());
+        assert_eq!(6, Store::open(&path, App::new()).unwrap().app().rule_set().rules.len());
+
+        let version = store.rollback(2).unwrap();
+        assert_eq!((4, "rollback to 2"), (version.number, version.change.as_str()));
+        assert_eq!(7, Store::open(&path, App::new()).unwrap().app().rule_set().rules.len());
+
+        // a file which can not be written keeps the current version
+        let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+        fs::create_dir(&tmp).unwrap();
+        let err = store.update("add", |app| app.add("!A && !B && C => H = P")).unwrap_err();
+        assert!(err.is::<SaveError>(), "{}", err);
+        assert_eq!(4, store.current().number);
+        fs::remove_dir(&tmp).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = temp("reload");
+        let store = Store::open(&path, App::default()).unwrap();
+        let mut watcher = Watcher::new(store.clone());
+        assert!(watcher.poll().unwrap().is_none());
+
+        // written by the store itself, same rules
+        store.update("add", |app| app.add("!A && !B && !C => H = P")).unwrap();
+        assert!(watcher.poll().unwrap().is_none());
+        assert_eq!(2, store.current().number);
+
+        let before = store.app();
+        fs::write(&path, r#"{"categories": ["M"], "rules": [{"source": "A => H = M"}, {"source": "H = M => K = D"}]}"#).unwrap();
+        let version = watcher.poll().unwrap().unwrap();
+        assert_eq!(3, version.number);
+        assert!(version.change.starts_with("reloaded"));
+        assert_eq!(2.5, store.app().run(&Scope::new(true, false, false, 2.5, 0, 0)).unwrap());
+        assert_eq!(7, before.rules().len());
+        assert!(watcher.poll().unwrap().is_none());
+
+        fs::write(&path, r#"{"categories": ["M"], "rules": [{"source": "A => H = M"}, {"source": "H = M => K = D +"}]}"#).unwrap();
+        assert!(watcher.poll().unwrap_err().to_string().contains("rules[1]:1:17"));
+        assert_eq!(3, store.current().number);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_versions() {
+        let store = Store::in_memory(App::default());
+        store.update("add B", |app| app.add("!A && B && !C => H = M")).unwrap();
+        store.update("remove 1", |app| app.remove_rule(1)).unwrap();
+        assert_eq!("category P is not produced by any rule",
+                   store.update("remove 2", |app| app.remove_rule(2)).unwrap_err().to_string());
+        let changes: Vec<(u64, String, usize)> = store.versions().iter()
+            .map(|v| (v.number, v.change.clone(), v.info().rules))
+            .collect();
+        assert_eq!(vec![
+            (1, "initial rules".to_string(), 6),
+            (2, "add B".to_string(), 7),
+            (3, "remove 1".to_string(), 6),
+        ], changes);
+
+        let old = store.version(1).unwrap();
+        assert_eq!(App::default(), *old.app);
+        assert!(store.rollback(7).is_err());
+        assert_eq!(3, store.current().number);
+
+        store.rollback(1).unwrap();
+        assert_eq!(App::default(), *store.app());
+        assert_eq!(4, store.current().number);
+    }
+
+    #[test]
+    fn test_retention() {
+        let store = Store::in_memory(App::default()).with_retention(3);
+        for i in 0..4 {
+            store.update("add", |app| app.add(&format!("X{} => H = M", i))).unwrap();
+        }
+        let numbers: Vec<u64> = store.versions().iter().map(|v| v.number).collect();
+        assert_eq!(vec![3, 4, 5], numbers);
+        assert_eq!("version 2 not found", store.rollback(2).unwrap_err().to_string());
+        assert_eq!(6, store.rollback(3).unwrap().number);
+        assert_eq!(4, store.versions()[0].number);
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let path = temp("invalid");
+        fs::write(&path, r#"{"categories": ["M"], "rules": [{"source": "A => H = M"}, {"source": "A && => H = M"}]}"#).unwrap();
+        let err = Store::open(&path, App::new()).err().unwrap().to_string();
+        assert!(err.starts_with("error: unexpected `=>`"), "{}", err);
+        assert!(err.contains(&format!("--> {}:rules[1]:1:6", path.display())), "{}", err);
+
+        fs::write(&path, "{").unwrap();
+        assert!(Store::open(&path, App::new()).err().unwrap().to_string().starts_with("invalid rule file"));
+        fs::remove_file(&path).unwrap();
+    }
+}
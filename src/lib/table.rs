@@ -0,0 +1,345 @@
+use anyhow::Result;
+use crate::lib::app::{App, Strategy};
+use crate::lib::expr::Expr;
+use crate::lib::parser::{LogicOp, Node};
+use crate::lib::rule::{Rule, RuleSource};
+use crate::lib::tokenize::is_ident;
+
+/// Base1 rules as a decision table, one column per input and one row per rule.
+/// A cell is `true`, `false` or `-` when the rule does not test the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionTable {
+    /// Set by a `hit policy` line, see `Strategy::from_hit_policy`
+    pub strategy: Option<Strategy>,
+    pub inputs: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// Line of the row in the csv, 0 for rows not read from one
+    pub line: usize,
+    pub cells: Vec<Option<bool>>,
+    pub category: String,
+    pub priority: i64,
+}
+
+impl Row {
+    /// `A && !C` for the cells `true, -, false`
+    fn condition(&self, inputs: &[String]) -> String {
+        let literals: Vec<String> = inputs.iter().zip(&self.cells)
+            .filter_map(|(name, cell)| cell.map(|v| if v { name.clone() } else { format!("!{}", name) }))
+            .collect();
+        literals.join(" && ")
+    }
+}
+
+/// Reads a csv decision table: an optional `hit policy,FIRST` line, the header naming the
+/// inputs, the `H` column with the category and an optional `priority` column, then the rows.
+/// `#` comments are skipped, every error of the table is reported with its line
+pub fn parse(text: &str) -> Result<DecisionTable> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let mut records = vec![];
+    for r in reader.records() {
+        let r = r.map_err(|e| anyhow!("invalid csv: {}", e))?;
+        records.push((r.position().map(|p| p.line() as usize).unwrap_or(0), r));
+    }
+    let mut records = records.into_iter().peekable();
+    let mut table = DecisionTable {
+        strategy: None,
+        inputs: vec![],
+        rows: vec![],
+    };
+    if let Some((line, r)) = records.peek() {
+        if r[0].eq_ignore_ascii_case("hit policy") {
+            let policy = r.get(1).unwrap_or("");
+            table.strategy = Some(Strategy::from_hit_policy(policy).ok_or_else(|| {
+                anyhow!("line {}: unknown hit policy `{}`, expected UNIQUE, FIRST, PRIORITY or COLLECT", line, policy)
+            })?);
+            records.next();
+        }
+    }
+    let (line, header) = records.next().ok_or_else(|| anyhow!("no header with the input columns and `H`"))?;
+    let mut errors = vec![];
+    let (mut output, mut priority) = (None, None);
+    let mut inputs = vec![];
+    for (i, name) in header.iter().enumerate() {
+        let seen = match name {
+            "H" => output.replace(i).is_some(),
+            "priority" => priority.replace(i).is_some(),
+            _ if is_ident(name) => {
+                let seen = table.inputs.iter().any(|x| x == name);
+                table.inputs.push(name.to_string());
+                inputs.push(i);
+                seen
+            },
+            _ => {
+                errors.push(format!("line {}: unknown column `{}`, expected an input name, `H` or `priority`", line, name));
+                false
+            },
+        };
+        if seen {
+            errors.push(format!("line {}: duplicate column `{}`", line, name));
+        }
+    }
+    let output = match output {
+        Some(output) => output,
+        None => {
+            errors.push(format!("line {}: no `H` column", line));
+            return Err(anyhow!(errors.join("\n")));
+        },
+    };
+    for (line, r) in records {
+        if r.len() != header.len() {
+            errors.push(format!("line {}: {} cells, expected {}", line, r.len(), header.len()));
+            continue;
+        }
+        let mut row = Row {
+            line,
+            cells: vec![],
+            category: r[output].to_string(),
+            priority: 0,
+        };
+        for (i, column) in inputs.iter().map(|i| (*i, &header[*i])) {
+            match &r[i] {
+                "true" => row.cells.push(Some(true)),
+                "false" => row.cells.push(Some(false)),
+                "-" | "" => row.cells.push(None),
+                other => errors.push(format!("line {}, column {}: expected true, false or -, found `{}`", line, column, other)),
+            }
+        }
+        if let Some(i) = priority {
+            match r[i].parse::<i64>() {
+                Ok(p) => row.priority = p,
+                Err(_) if r[i].is_empty() => {},
+                Err(_) => errors.push(format!("line {}, column priority: expected an integer, found `{}`", line, &r[i])),
+            }
+        }
+        if row.category.is_empty() {
+            errors.push(format!("line {}: no category in `H`", line));
+        }
+        table.rows.push(row);
+    }
+    if errors.is_empty() {
+        return Ok(table);
+    }
+    Err(anyhow!(errors.join("\n")))
+}
+
+/// Inputs a condition made only of `&&` over inputs and negated inputs tests, `None` for any other condition
+fn literals(n: &Node, out: &mut Vec<(String, bool)>) -> Option<()> {
+    match n {
+        Node::Var(name) => out.push((name.clone(), true)),
+        Node::Not(x) => match &**x {
+            Node::Var(name) => out.push((name.clone(), false)),
+            _ => return None,
+        },
+        Node::Logic(LogicOp::And, l, r) => {
+            literals(l, out)?;
+            literals(r, out)?;
+        },
+        _ => return None,
+    }
+    Some(())
+}
+
+impl DecisionTable {
+    /// Table of the Base1 rules, fails on a condition which is not a conjunction of inputs
+    /// or tests an input both ways
+    pub fn from_app(app: &App) -> Result<DecisionTable> {
+        let mut table = DecisionTable {
+            strategy: Some(app.strategy()),
+            inputs: vec![],
+            rows: vec![],
+        };
+        let mut conditions = vec![];
+        for r in app.rules() {
+            let e = match r.expr() {
+                Expr::Base1(e) => e,
+                Expr::Base2(_) => continue,
+            };
+            let mut tests = vec![];
+            if literals(e.condition(), &mut tests).is_none() {
+                return Err(anyhow!("rule {} `{}` is not a conjunction of inputs", r.id(), r.source()));
+            }
+            for (name, _) in &tests {
+                if !table.inputs.contains(name) {
+                    table.inputs.push(name.clone());
+                }
+            }
+            conditions.push((r, e.output().name().to_string(), tests));
+        }
+        for (r, category, tests) in conditions {
+            let mut cells = vec![None; table.inputs.len()];
+            for (name, value) in tests {
+                let i = table.inputs.iter().position(|x| *x == name).unwrap_or(0);
+                if cells[i] == Some(!value) {
+                    return Err(anyhow!("rule {} `{}` tests {} both ways", r.id(), r.source(), name));
+                }
+                cells[i] = Some(value);
+            }
+            table.rows.push(Row {
+                line: 0,
+                cells,
+                category,
+                priority: r.priority(),
+            });
+        }
+        Ok(table)
+    }
+
+    /// The table as csv, the `priority` column is only written when a row has one
+    pub fn to_csv(&self) -> Result<String> {
+        let mut w = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
+        if let Some(s) = self.strategy {
+            w.write_record(["hit policy", s.hit_policy()])?;
+        }
+        let priorities = self.rows.iter().any(|r| r.priority != 0);
+        let mut header: Vec<&str> = self.inputs.iter().map(String::as_str).collect();
+        header.push("H");
+        if priorities {
+            header.push("priority");
+        }
+        w.write_record(&header)?;
+        for row in &self.rows {
+            let mut record: Vec<String> = row.cells.iter().map(|c| match c {
+                Some(v) => v.to_string(),
+                None => "-".to_string(),
+            }).collect();
+            record.push(row.category.clone());
+            if priorities {
+                record.push(row.priority.to_string());
+            }
+            w.write_record(&record)?;
+        }
+        let bytes = w.into_inner().map_err(|e| anyhow!("{}", e))?;
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    /// Replaces the Base1 rules of `base` with the rows in their order, keeping its formulas.
+    /// A row building the same program as a current rule keeps its id and metadata.
+    /// The hit policy replaces the strategy when the table has one
+    pub fn load(&self, base: &App) -> Result<App> {
+        let mut set = base.rule_set();
+        if let Some(s) = self.strategy {
+            set.strategy = s;
+        }
+        let mut rules = vec![];
+        let mut errors = vec![];
+        for (index, row) in self.rows.iter().enumerate() {
+            if row.cells.iter().all(Option::is_none) {
+                errors.push(format!("line {}: no input is tested", row.line));
+                continue;
+            }
+            if let Some(same) = self.rows[..index].iter().find(|r| r.cells == row.cells) {
+                errors.push(format!("line {}: same inputs as line {}", row.line, same.line));
+                continue;
+            }
+            let source = RuleSource {
+                source: format!("{} => H = {}", row.condition(&self.inputs), row.category),
+                priority: row.priority,
+                ..RuleSource::default()
+            };
+            let rule = match base.parse_rule(&source) {
+                Ok(rule) => rule,
+                Err(e) => {
+                    errors.push(format!("line {}: {}", row.line, e));
+                    continue;
+                },
+            };
+            rules.push(match base.rules().iter().find(|r| r.expr() == rule.expr()) {
+                Some(old) => RuleSource {
+                    source: source.source,
+                    priority: source.priority,
+                    ..old.to_source()
+                },
+                None => source,
+            });
+        }
+        if !errors.is_empty() {
+            return Err(anyhow!(errors.join("\n")));
+        }
+        let rows = rules.len();
+        rules.extend(base.rules().iter().filter(|r| matches!(r.expr(), Expr::Base2(_))).map(Rule::to_source));
+        set.rules = rules;
+        base.with_rule_set(&set).map_err(|errors| {
+            let all: Vec<String> = errors.iter().map(|e| match self.rows.get(e.rule) {
+                Some(row) if e.rule < rows => format!("line {}: {}", row.line, e.message),
+                _ => e.message.clone(),
+            }).collect();
+            anyhow!(all.join("\n"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lib::app::{App, Strategy};
+    use crate::lib::scope::Scope;
+    use super::parse;
+
+    const TABLE: &str = "hit policy,COLLECT
+# inputs, category and priority
+A,     B,     C,     H, priority
+true,  true,  false, M, 0
+true,  true,  true,  P, 0
+false, true,  true,  T, 0
+-,     false, -,     P, 2
+";
+
+    #[test]
+    fn test_parse() {
+        let table = parse(TABLE).unwrap();
+        assert_eq!(Some(Strategy::CollectAll), table.strategy);
+        assert_eq!(vec!["A", "B", "C"], table.inputs);
+        assert_eq!((vec![None, Some(false), None], "P", 2, 7),
+                   (table.rows[3].cells.clone(), table.rows[3].category.as_str(), table.rows[3].priority, table.rows[3].line));
+
+        let err = |s: &str| parse(s).unwrap_err().to_string();
+        assert_eq!("line 1: unknown hit policy `ANY`, expected UNIQUE, FIRST, PRIORITY or COLLECT", err("hit policy,ANY\nA,H"));
+        assert_eq!("line 1: unknown column `order total`, expected an input name, `H` or `priority`\n\
+                    line 1: duplicate column `A`\n\
+                    line 1: unknown column `K`, expected an input name, `H` or `priority`",
+                   err("A,order total,A,K,H\n"));
+        assert_eq!("line 1: no `H` column", err("A,B"));
+        assert_eq!("line 2, column B: expected true, false or -, found `yes`\nline 3: 2 cells, expected 3\nline 4: no category in `H`",
+                   err("A,B,H\ntrue,yes,M\ntrue,M\nfalse,-,"));
+    }
+
+    #[test]
+    fn test_import() {
+        let app = App::default().import_table(TABLE).unwrap();
+        let sources: Vec<(u64, &str)> = app.rules().iter().map(|r| (r.id(), r.source())).collect();
+        assert_eq!(vec![
+            (1, "A && B && !C => H = M"),
+            (2, "A && B && C => H = P"),
+            (3, "!A && B && C => H = T"),
+            (7, "!B => H = P"),
+            (4, "H = M => K = D + (D * E / 10)"),
+            (5, "H = P => K = D + (D * (E - F) / 25.5)"),
+            (6, "H = T => K = D - (D * F / 30)"),
+        ], sources);
+        assert_eq!(Strategy::CollectAll, app.strategy());
+        assert_eq!(3.0, app.run(&Scope::new(true, true, true, 1.0, 52, 1)).unwrap());
+
+        let err = |s: &str| App::default().import_table(s).unwrap_err().to_string();
+        assert_eq!("line 2: no input is tested\nline 4: same inputs as line 3", err("A,H\n-,M\ntrue,M\ntrue,P"));
+        assert_eq!("line 2: category GOLD is not declared", err("A,H\ntrue,GOLD"));
+    }
+
+    #[test]
+    fn test_export() {
+        assert_eq!("hit policy,FIRST\nA,B,C,H\ntrue,true,false,M\ntrue,true,true,P\nfalse,true,true,T\n",
+                   App::default().export_table().unwrap());
+        let app = App::default().import_table(TABLE).unwrap();
+        assert_eq!(app, app.import_table(&app.export_table().unwrap()).unwrap());
+
+        assert_eq!("rule 7 `A || B => H = M` is not a conjunction of inputs",
+                   App::default().add("A || B => H = M").unwrap().export_table().unwrap_err().to_string());
+    }
+}
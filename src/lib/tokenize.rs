@@ -1,119 +1,336 @@
-use anyhow::Result;
+use serde::Serialize;
+use std::fmt;
 use crate::lib::tokenize::Token::Const;
+use crate::lib::error::{ErrorKind, SyntaxError};
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    A,
-    B,
-    C,
-    D,
-    E,
-    F,
     H,
-    M,
-    P,
-    T,
     K,
+    Ident(String),
     Not,
     Arrow,
     Eq,
     And,
+    Or,
     Plus,
     Minus,
     Open,
     Close,
     Multiple,
     Divide,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    EqEq,
+    Ne,
+    Comma,
     Const(f64),
+    Str(String),
+    OpenBrace,
+    CloseBrace,
 }
 
-pub fn tokenize(str: &str) -> Result<Vec<Token>> {
-    let mut out: Vec<Token> = vec![];
+/// How the token reads in error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Token::Ident(name) => return write!(f, "identifier `{}`", name),
+            Token::Const(v) => return write!(f, "number `{}`", v),
+            Token::Str(s) => return write!(f, "string \"{}\"", s),
+            Token::H => "H",
+            Token::K => "K",
+            Token::Not => "!",
+            Token::Arrow => "=>",
+            Token::Eq => "=",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Open => "(",
+            Token::Close => ")",
+            Token::Multiple => "*",
+            Token::Divide => "/",
+            Token::Lt => "<",
+            Token::Le => "<=",
+            Token::Gt => ">",
+            Token::Ge => ">=",
+            Token::EqEq => "==",
+            Token::Ne => "!=",
+            Token::Comma => ",",
+            Token::OpenBrace => "{",
+            Token::CloseBrace => "}",
+        };
+        write!(f, "`{}`", symbol)
+    }
+}
+
+/// Byte range of a token in the source string
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+        }
+    }
+
+    /// Smallest span covering both
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned {
+    pub token: Token,
+    pub span: Span,
+}
+
+pub fn tokenize(str: &str) -> Result<Vec<Spanned>, SyntaxError> {
+    let (tokens, mut errors) = tokenize_all(str);
+    if errors.is_empty() {
+        return Ok(tokens);
+    }
+    Err(errors.remove(0))
+}
+
+/// A single identifier other than `H` and `K`
+pub fn is_ident(name: &str) -> bool {
+    match tokenize(name).as_ref().map(|t| t.as_slice()) {
+        Ok([t]) => matches!(t.token, Token::Ident(_)),
+        _ => false,
+    }
+}
+
+/// Tokenizes the whole string, a character or number which can not be read is
+/// reported and skipped
+pub fn tokenize_all(str: &str) -> (Vec<Spanned>, Vec<SyntaxError>) {
+    let mut out: Vec<Spanned> = vec![];
+    let mut errors = vec![];
     let iter: Vec<char> = str.chars().collect();
+    // byte offset of every char and of the end of the string
+    let offsets: Vec<usize> = str.char_indices().map(|(i, _)| i).chain(Some(str.len())).collect();
+    let span = |start: usize, last: usize| Span::new(offsets[start], offsets[last + 1]);
     let mut pos: usize = 0;
     loop {
         let v = iter.get(pos);
         if v.is_none() {
-            return Ok(out);
+            return (out, errors);
         }
-        match v.unwrap() {
-            ' ' => { pos += 1; continue},
-            'A' => out.push(Token::A),
-            'B' => out.push(Token::B),
-            'C' => out.push(Token::C),
-            'D' => out.push(Token::D),
-            'F' => out.push(Token::F),
-            'E' => out.push(Token::E),
-            'H' => out.push(Token::H),
-            'M' => out.push(Token::M),
-            'K' => out.push(Token::K),
-            'P' => out.push(Token::P),
-            'T' => out.push(Token::T),
-            '!' => out.push(Token::Not),
-            '=' => out.push(Token::Eq),
-            '+' => out.push(Token::Plus),
-            '-' => out.push(Token::Minus),
-            '*' => out.push(Token::Multiple),
-            '/' => out.push(Token::Divide),
-            '(' => out.push(Token::Open),
-            ')' => out.push(Token::Close),
-            '0'..='9'  => {
+        let start = pos;
+        let token = match v.unwrap() {
+            ' ' | '\t' | '\r' | '\n' => { pos += 1; continue},
+            '#' => {
+                while pos < iter.len() && iter[pos] != '\n' {
+                    pos += 1;
+                }
+                continue
+            },
+            '"' => {
+                let mut str = String::new();
+                while let Some(c) = iter.get(pos + 1) {
+                    if *c == '"' || *c == '\n' {
+                        break
+                    }
+                    str.push(*c);
+                    pos += 1;
+                }
+                if iter.get(pos + 1) != Some(&'"') {
+                    errors.push(SyntaxError::new(ErrorKind::UnterminatedString, span(start, pos)).expected(&["`\"`"]));
+                    pos += 1;
+                    continue
+                }
+                pos += 1;
+                Token::Str(str)
+            },
+            'a'..='z' | 'A'..='Z' | '_' => {
                 let mut str = String::new();
                 str.push(iter[pos]);
-                loop {
-                    if let None = iter.get(pos + 1) {
+                while let Some(c) = iter.get(pos + 1) {
+                    if !c.is_ascii_alphanumeric() && *c != '_' {
                         break
                     }
-                    match iter[pos + 1] {
-                        '0'..='9' | '.' => {
-                            str.push(iter[pos + 1])
-                        },
+                    str.push(*c);
+                    pos += 1;
+                }
+                match str.as_str() {
+                    "H" => Token::H,
+                    "K" => Token::K,
+                    _ => Token::Ident(str),
+                }
+            },
+            '!' => match iter.get(pos + 1) {
+                Some('=') => { pos += 1; Token::Ne },
+                _ => Token::Not,
+            },
+            '=' => match iter.get(pos + 1) {
+                Some('>') => { pos += 1; Token::Arrow },
+                Some('=') => { pos += 1; Token::EqEq },
+                _ => Token::Eq,
+            },
+            '<' => match iter.get(pos + 1) {
+                Some('=') => { pos += 1; Token::Le },
+                _ => Token::Lt,
+            },
+            '>' => match iter.get(pos + 1) {
+                Some('=') => { pos += 1; Token::Ge },
+                _ => Token::Gt,
+            },
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Multiple,
+            '/' => Token::Divide,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '{' => Token::OpenBrace,
+            '}' => Token::CloseBrace,
+            '0'..='9'  => {
+                let mut str = String::new();
+                str.push(iter[pos]);
+                while let Some(c) = iter.get(pos + 1) {
+                    match c {
+                        '0'..='9' | '.' => str.push(*c),
                         _ => break,
                     }
                     pos += 1;
                 }
-                out.push(Const(str.parse::<f64>()?))
+                match str.parse::<f64>() {
+                    Ok(v) => Const(v),
+                    Err(_) => {
+                        errors.push(SyntaxError::new(ErrorKind::InvalidNumber, span(start, pos)).found(format!("`{}`", str)));
+                        pos += 1;
+                        continue
+                    },
+                }
             },
-            '&' => {
-                pos += 1;
-                match iter.get(pos) {
-                    Some('&') => out.push(Token::And),
-                    Some(t) => return Err(anyhow!("unexpected char '{}' at pos {}", t, pos)),
-                    None => return Err(anyhow!("unexpected eof at pos {}", pos)),
+            '&' | '|' => {
+                let c = iter[pos];
+                if iter.get(pos + 1) != Some(&c) {
+                    let doubled = format!("`{}{}`", c, c);
+                    errors.push(SyntaxError::new(ErrorKind::InvalidCharacter, span(pos, pos))
+                        .found(format!("`{}`", c))
+                        .expected(&[&doubled]));
+                    pos += 1;
+                    continue
                 }
+                pos += 1;
+                if c == '&' { Token::And } else { Token::Or }
             },
-            '>' => {
-                    match out.pop() {
-                        Some(Token::Eq) => out.push(Token::Arrow),
-                        _ => return Err(anyhow!("invalid value '{}' at pos {}", v.unwrap(), pos)),
-                    }
-                },
-
-            _   => return Err(anyhow!("tokenize: invalid value '{}' at pos {}", v.unwrap(), pos)),
-        }
+            c => {
+                errors.push(SyntaxError::new(ErrorKind::InvalidCharacter, span(pos, pos)).found(format!("`{}`", c)));
+                pos += 1;
+                continue
+            },
+        };
+        out.push(Spanned {
+            token,
+            span: span(start, pos),
+        });
         pos += 1;
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::lib::tokenize::tokenize;
+    use crate::lib::error::SyntaxError;
+    use crate::lib::tokenize::{Span, Token};
+
+    fn id(s: &str) -> Token {
+        Token::Ident(s.to_string())
+    }
+
+    fn tokenize(s: &str) -> Result<Vec<Token>, SyntaxError> {
+        Ok(super::tokenize(s)?.into_iter().map(|t| t.token).collect())
+    }
 
     #[test]
     fn check_tokenize() {
         use super::Token::*;
 
         assert_eq!(vec![And], tokenize("&&").unwrap());
-        assert_eq!(vec![A, And, B], tokenize("A && B ").unwrap());
-        assert_eq!(vec![Not, C, Arrow, H, Eq, M], tokenize(" !C => H = M").unwrap());
-        assert_eq!(vec![A, And, B, And, Not, C, Arrow, H, Eq, M], tokenize("A && B && !C => H = M").unwrap());
+        assert_eq!(vec![id("A"), And, id("B")], tokenize("A && B ").unwrap());
+        assert_eq!(vec![Not, id("C"), Arrow, H, Eq, id("M")], tokenize(" !C => H = M").unwrap());
+        assert_eq!(vec![id("A"), And, id("B"), And, Not, id("C"), Arrow, H, Eq, id("M")],
+                   tokenize("A && B && !C => H = M").unwrap());
+        assert_eq!(vec![Open, id("A"), Or, id("B"), Close, And, Not, id("C")], tokenize("(A || B) && !C").unwrap());
+        assert!(tokenize("A | B").is_err());
     }
 
     #[test]
     fn check_tokenize2() {
         use super::Token::*;
-        assert_eq!(vec![H, Eq, P, Arrow, K, Eq, D, Plus, Open, D, Multiple,
-                        Open, E, Minus, F, Close, Divide, Const(25.5), Close],
+        assert_eq!(vec![H, Eq, id("P"), Arrow, K, Eq, id("D"), Plus, Open, id("D"), Multiple,
+                        Open, id("E"), Minus, id("F"), Close, Divide, Const(25.5), Close],
                    tokenize("H = P => K = D + (D * (E - F) / 25.5)").unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn check_identifiers() {
+        use super::Token::*;
+        assert_eq!(vec![id("is_vip"), And, Not, id("_flag2"), Arrow, H, Eq, id("GOLD")],
+                   tokenize("is_vip&&!_flag2 => H = GOLD").unwrap());
+        assert_eq!(vec![K, Eq, id("order_total"), Multiple, id("items_count"), Minus, id("HK")],
+                   tokenize("K = order_total * items_count - HK").unwrap());
+        assert_eq!(vec![K, Eq, id("round"), Open, id("D"), Multiple, Const(1.2), Comma, Const(2.0), Close],
+                   tokenize("K = round(D * 1.2, 2)").unwrap());
+        assert!(tokenize("order.total").is_err());
+    }
+
+    #[test]
+    fn check_comparisons() {
+        use super::Token::*;
+        assert_eq!(vec![id("A"), And, id("D"), Gt, Const(100.0), And, id("E"), Le, Const(3.0), Arrow, H, Eq, id("P")],
+                   tokenize("A && D > 100 && E <= 3 => H = P").unwrap());
+        assert_eq!(vec![id("D"), Ge, id("E"), Arrow, id("D"), EqEq, id("E"), Ne, Not, id("F")],
+                   tokenize("D>=E=>D==E!=!F").unwrap());
+        assert_eq!(vec![id("D"), Ge, Const(1.0), Arrow, H, Eq, id("M")], tokenize("D >= 1 => H = M").unwrap());
+        assert_eq!(vec![Not, Open, id("D"), EqEq, Const(1.0), Close], tokenize("!(D == 1)").unwrap());
+        assert_eq!(vec![id("D"), Lt, Const(2.0), Or, id("D"), Ne, Const(5.0)], tokenize("D<2||D!=5").unwrap());
+    }
+
+    #[test]
+    fn check_spans() {
+        let spans: Vec<(usize, usize)> = super::tokenize("A && D>=10.5 => H = M").unwrap()
+            .into_iter().map(|t| (t.span.start, t.span.end)).collect();
+        assert_eq!(vec![(0, 1), (2, 4), (5, 6), (6, 8), (8, 12), (13, 15), (16, 17), (18, 19), (20, 21)], spans);
+
+        let e = super::tokenize("A & B").unwrap_err();
+        assert_eq!(("invalid character `&`, expected `&&`".to_string(), Span::new(2, 3)), (e.to_string(), e.span));
+        let e = super::tokenize("A && é").unwrap_err();
+        assert_eq!(("invalid character `é`".to_string(), Span::new(5, 7)), (e.to_string(), e.span));
+        let e = super::tokenize("D > 1.2.3").unwrap_err();
+        assert_eq!(("invalid number `1.2.3`".to_string(), Span::new(4, 9)), (e.to_string(), e.span));
+    }
+
+    #[test]
+    fn check_file_tokens() {
+        use Token::*;
+        let source = "# pricing\nrule \"vip\" {\n\tis_vip # flag\n\t\t=> H = M\n}\n";
+        assert_eq!(vec![id("rule"), Str("vip".into()), OpenBrace, id("is_vip"), Arrow, H, Eq, id("M"), CloseBrace],
+                   tokenize(source).unwrap());
+
+        let e = super::tokenize("owner \"team\nA").unwrap_err();
+        assert_eq!(("unterminated string, expected `\"`".to_string(), Span::new(6, 11)), (e.to_string(), e.span));
+    }
+
+    #[test]
+    fn check_recovery() {
+        let (tokens, errors) = super::tokenize_all("A & B || 1.2.3 ~ C");
+        let tokens: Vec<Token> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(vec![Token::Ident("A".into()), Token::Ident("B".into()), Token::Or, Token::Ident("C".into())], tokens);
+        let errors: Vec<(String, Span)> = errors.into_iter().map(|e| (e.to_string(), e.span)).collect();
+        assert_eq!(vec![
+            ("invalid character `&`, expected `&&`".to_string(), Span::new(2, 3)),
+            ("invalid number `1.2.3`".to_string(), Span::new(9, 14)),
+            ("invalid character `~`".to_string(), Span::new(15, 16)),
+        ], errors);
+    }
+}
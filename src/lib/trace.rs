@@ -0,0 +1,48 @@
+use serde::Serialize;
+use crate::lib::analysis::RuleRef;
+use crate::lib::app::Strategy;
+
+/// Stack of a numeric program right after one of its instructions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Step {
+    pub op: String,
+    pub stack: Vec<f64>,
+}
+
+/// Base1 rule tested by `App::explain` and whether its condition held, `error` is set
+/// when the condition could not be evaluated
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleTrace {
+    pub rule: RuleRef,
+    pub priority: i64,
+    pub matched: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Base2 formula evaluated for a selected category, `value` is missing when it failed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FormulaTrace {
+    pub index: usize,
+    pub formula: String,
+    pub steps: Vec<Step>,
+    pub value: Option<f64>,
+}
+
+/// Rule picked by the strategy, `formula` is missing when the category has none
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Selection {
+    pub rule: usize,
+    pub category: String,
+    pub reason: String,
+    pub formula: Option<FormulaTrace>,
+}
+
+/// Everything `App::run_all` did for one scope, up to the error which stopped it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Explanation {
+    pub strategy: Strategy,
+    pub rules: Vec<RuleTrace>,
+    pub selected: Vec<Selection>,
+    pub error: Option<String>,
+}
@@ -6,7 +6,21 @@ extern crate anyhow;
 extern crate rocket;
 extern crate serde;
 use rocket::State;
-use crate::lib::app::App;
+use rocket::http::Status;
+use rocket::response::content::Xml;
+use rocket::response::status::{BadRequest, Custom, NotFound};
+use crate::lib::app::{App, Strategy};
+use crate::lib::analysis::{Coverage, Diagnostic, Overlap};
+use crate::lib::error::{render_all, RuleError, SyntaxErrors};
+use crate::lib::rule::{Rule, RuleSet, RuleSource};
+use crate::lib::document::Format;
+use crate::lib::jsonlogic::LogicRule;
+use crate::lib::store::{SaveError, Store, Watcher};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use crate::lib::history::{diff, Diff, Version, VersionInfo};
+use crate::lib::trace::Explanation;
 use crate::lib::scope::Scope;
 use serde::Deserialize;
 use rocket_contrib::json::Json;
@@ -18,55 +32,375 @@ struct Req {
     exprs: Vec<String>,
 }
 
-#[get("/<a>/<b>/<c>/<d>/<e>/<f>")]
-fn req_get(app: State<App>, a: bool, b: bool, c: bool, d: f64, e: i64, f: i64) -> String {
-    let scope = Scope::new(a, b, c, d, e, f);
-    match app.run(scope) {
-        Ok(f) => format!("Ok: {}", f),
+#[derive(Deserialize)]
+struct EvalReq {
+    scope: Scope,
+    #[serde(default)]
+    categories: Vec<String>,
+    strategy: Option<Strategy>,
+    #[serde(default)]
+    exprs: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RulesReq {
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    exprs: Vec<String>,
+}
+
+/// Adds the rules in order, the errors of every bad rule are rendered with
+/// the offending part of the rule underlined
+fn with_exprs(app: &App, exprs: &[String]) -> Result<App, anyhow::Error> {
+    app.add_all(exprs).map_err(|errors| anyhow!(render_all(&errors, exprs, "exprs")))
+}
+
+/// Formats the decisions, `Ok: K` for a single one and `Ok: M = K1, P = K2` for several
+fn render(app: &App, scope: &Scope) -> String {
+    match app.run_all(scope) {
+        Ok(ref rs) if rs.len() == 1 => format!("Ok: {}", rs[0].1),
+        Ok(rs) => {
+            let all: Vec<String> = rs.iter().map(|(h, k)| format!("{} = {}", h, k)).collect();
+            format!("Ok: {}", all.join(", "))
+        },
         Err(e) => e.to_string()
     }
 }
 
+#[get("/<a>/<b>/<c>/<d>/<e>/<f>")]
+fn req_get(store: State<Store>, a: bool, b: bool, c: bool, d: f64, e: i64, f: i64) -> String {
+    let scope = Scope::new(a, b, c, d, e, f);
+    render(&store.app(), &scope)
+}
+
 #[post("/<a>/<b>/<c>/<d>/<e>/<f>", data = "<exprs>")]
-fn req_post(app: State<App>, a: bool, b: bool, c: bool, d: f64, e: i64, f: i64, exprs: Json<Req>) -> String {
-    let app: Result<App, anyhow::Error> = exprs.exprs.iter().fold(Ok(app.clone()), |app, cur| {
-        match app {
-            Ok(a) => a.add(cur),
-            Err(e) => Err(e)
-        }
-    });
+fn req_post(store: State<Store>, a: bool, b: bool, c: bool, d: f64, e: i64, f: i64, exprs: Json<Req>) -> String {
+    let app = with_exprs(&store.app(), &exprs.exprs);
 
     if app.is_err() {
         return app.unwrap_err().to_string();
     }
 
     let scope = Scope::new(a, b, c, d, e, f);
-    match app.unwrap().run(scope) {
-        Ok(f) => format!("Ok: {}", f),
-        Err(e) => e.to_string()
+    render(&app.unwrap(), &scope)
+}
+
+/// Served app with the categories, strategy and rules of the request
+fn eval_app(app: &App, req: &EvalReq) -> Result<App, anyhow::Error> {
+    let app = req.categories.iter().fold(app.clone(), |app, cur| app.category(cur));
+    let app = match req.strategy {
+        Some(s) => app.with_strategy(s),
+        None => app,
+    };
+    with_exprs(&app, &req.exprs)
+}
+
+#[post("/eval", data = "<req>")]
+fn req_eval(store: State<Store>, req: Json<EvalReq>) -> String {
+    match eval_app(&store.app(), &req) {
+        Ok(app) => render(&app, &req.scope),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Trace of the evaluation, same request as `/eval`
+#[post("/explain", data = "<req>")]
+fn req_explain(store: State<Store>, req: Json<EvalReq>) -> Result<Json<Explanation>, BadRequest<String>> {
+    eval_app(&store.app(), &req)
+        .map(|app| Json(app.explain(&req.scope)))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Served app extended with the categories and rules of the request
+fn with_rules(app: &App, req: &RulesReq) -> Result<App, anyhow::Error> {
+    let app = req.categories.iter().fold(app.clone(), |app, cur| app.category(cur));
+    with_exprs(&app, &req.exprs)
+}
+
+/// Overlapping rules of the served app extended with the given categories and rules
+#[post("/analyze", data = "<req>")]
+fn req_analyze(store: State<Store>, req: Json<RulesReq>) -> Result<Json<Vec<Overlap>>, BadRequest<String>> {
+    with_rules(&store.app(), &req)
+        .and_then(|app| app.overlaps())
+        .map(Json)
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Uncovered inputs and categories without formula, same request as `/analyze`.
+/// The coverage of the stored rules alone is computed once per version
+#[post("/coverage", data = "<req>")]
+fn req_coverage(store: State<Store>, req: Json<RulesReq>) -> Result<Json<Coverage>, BadRequest<String>> {
+    let coverage = if req.categories.is_empty() && req.exprs.is_empty() {
+        store.current().coverage()
+    } else {
+        with_rules(&store.app(), &req).and_then(|app| app.coverage())
+    };
+    coverage.map(Json).map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Lint diagnostics, same request as `/analyze`
+#[post("/lint", data = "<req>")]
+fn req_lint(store: State<Store>, req: Json<RulesReq>) -> Result<Json<Vec<Diagnostic>>, BadRequest<String>> {
+    with_rules(&store.app(), &req)
+        .and_then(|app| app.lint())
+        .map(Json)
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Every error of the given rules with its index and span, empty when all of them build
+#[post("/check", data = "<req>")]
+fn req_check(store: State<Store>, req: Json<RulesReq>) -> Json<Vec<RuleError>> {
+    let app = req.categories.iter().fold(App::clone(&store.app()), |app, cur| app.category(cur));
+    Json(app.add_all(&req.exprs).err().unwrap_or_default())
+}
+
+/// Stored rule set the service evaluates
+#[get("/rules")]
+fn rules_get(store: State<Store>) -> Json<RuleSet> {
+    Json(store.app().rule_set())
+}
+
+/// Adds the categories and rules of the request to the stored rule set
+#[post("/rules", data = "<req>")]
+fn rules_post(store: State<Store>, req: Json<RulesReq>) -> Result<Json<RuleSet>, BadRequest<String>> {
+    store.update("add rules", |app| with_rules(app, &req))
+        .map(|v| Json(v.app.rule_set()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Replaces the stored rule set, nothing changes unless every rule builds
+#[put("/rules", data = "<set>")]
+fn rules_put(store: State<Store>, set: Json<RuleSet>) -> Result<Json<RuleSet>, BadRequest<String>> {
+    let sources: Vec<&str> = set.rules.iter().map(|r| r.source.as_str()).collect();
+    store.update("replace rule set", |app| app.with_rule_set(&set).map_err(|errors| anyhow!(render_all(&errors, &sources, "rules"))))
+        .map(|v| Json(v.app.rule_set()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Stored rule set as a json, yaml or toml document, `?trees=true` writes conditions
+/// and formulas as syntax trees
+#[get("/rules/export/<format>?<trees>")]
+fn rules_export(store: State<Store>, format: String, trees: Option<bool>) -> Result<String, BadRequest<String>> {
+    Format::from_name(&format)
+        .and_then(|format| store.app().export(format, trees.unwrap_or(false)))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Replaces the stored rule set with the one of a json, yaml or toml document
+#[put("/rules/import/<format>", data = "<text>")]
+fn rules_import(store: State<Store>, format: String, text: String) -> Result<Json<RuleSet>, BadRequest<String>> {
+    let format = Format::from_name(&format).map_err(|e| BadRequest(Some(e.to_string())))?;
+    store.update(&format!("import {}", format), |app| app.import(&text, format))
+        .map(|v| Json(v.app.rule_set()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Base1 rules of the stored rule set as a csv decision table
+#[get("/table")]
+fn table_get(store: State<Store>) -> Result<String, BadRequest<String>> {
+    store.app().export_table().map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Replaces the stored Base1 rules with the rows of a csv decision table
+#[put("/table", data = "<text>")]
+fn table_put(store: State<Store>, text: String) -> Result<Json<RuleSet>, BadRequest<String>> {
+    store.update("import decision table", |app| app.import_table(&text))
+        .map(|v| Json(v.app.rule_set()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Stored rules as a DMN document
+#[get("/dmn")]
+fn dmn_get(store: State<Store>) -> Result<Xml<String>, BadRequest<String>> {
+    store.app().export_dmn().map(Xml).map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Replaces the stored rules with the decision table and literal expressions of a DMN document
+#[put("/dmn", data = "<xml>")]
+fn dmn_put(store: State<Store>, xml: String) -> Result<Json<RuleSet>, BadRequest<String>> {
+    store.update("import DMN", |app| app.import_dmn(&xml))
+        .map(|v| Json(v.app.rule_set()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Stored rules with conditions and formulas as JSON Logic
+#[get("/logic")]
+fn logic_get(store: State<Store>) -> Json<Vec<LogicRule>> {
+    Json(store.app().logic_rules())
+}
+
+/// Adds rules written as JSON Logic, a rule with an id replaces that rule
+#[post("/logic", data = "<rules>")]
+fn logic_post(store: State<Store>, rules: Json<Vec<LogicRule>>) -> Result<Json<Vec<LogicRule>>, BadRequest<String>> {
+    store.update("add JSON Logic rules", |app| app.add_logic(&rules))
+        .map(|v| Json(v.app.logic_rules()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Stored rule with its id and metadata
+#[get("/rules/<id>")]
+fn rule_get(store: State<Store>, id: u64) -> Option<Json<RuleSource>> {
+    store.app().rule(id).map(|r| Json(r.to_source()))
+}
+
+/// Replaces the rule with the id, or adds it under this id
+#[put("/rules/<id>", data = "<rule>")]
+fn rule_put(store: State<Store>, id: u64, rule: Json<RuleSource>) -> Result<Json<RuleSource>, BadRequest<String>> {
+    let version = store.update(&format!("put rule {}", id), |app| {
+        let r = app.parse_rule(&rule).map_err(|e| anyhow!(render_rule_error(e, &rule.source)))?;
+        app.put_rule(id, r)
+    }).map_err(|e| BadRequest(Some(e.to_string())))?;
+    version.app.rule(id).map(|r| Json(r.to_source())).ok_or_else(|| BadRequest(Some(format!("rule {} not found", id))))
+}
+
+/// Removes the rule with the id and returns it. A removal leaving a formula without
+/// rule is a bad request, a rule file which can not be written a server error
+#[delete("/rules/<id>")]
+fn rule_delete(store: State<Store>, id: u64) -> Result<Json<RuleSource>, Custom<String>> {
+    let mut removed = None;
+    store.update(&format!("remove rule {}", id), |app| {
+        removed = app.rule(id).map(Rule::to_source);
+        app.remove_rule(id)
+    }).map_err(|e| match removed {
+        None => Custom(Status::NotFound, e.to_string()),
+        Some(_) if e.is::<SaveError>() => Custom(Status::InternalServerError, e.to_string()),
+        Some(_) => Custom(Status::BadRequest, e.to_string()),
+    })?;
+    removed.map(Json).ok_or_else(|| Custom(Status::NotFound, format!("rule {} not found", id)))
+}
+
+/// Versions of the stored rule set, oldest first
+#[get("/versions")]
+fn versions_get(store: State<Store>) -> Json<Vec<VersionInfo>> {
+    Json(store.versions().iter().map(|v| v.info()).collect())
+}
+
+/// Rule set of a version
+#[get("/versions/<number>")]
+fn version_get(store: State<Store>, number: u64) -> Option<Json<RuleSet>> {
+    store.version(number).map(|v| Json(v.app.rule_set()))
+}
+
+/// Changes going from version `from` to version `to`
+#[get("/versions/<from>/diff/<to>")]
+fn version_diff(store: State<Store>, from: u64, to: u64) -> Result<Json<Diff>, NotFound<String>> {
+    let a = store.version(from).ok_or_else(|| NotFound(format!("version {} not found", from)))?;
+    let b = store.version(to).ok_or_else(|| NotFound(format!("version {} not found", to)))?;
+    Ok(Json(diff(&a.app, &b.app)))
+}
+
+/// Same as `/eval` with the rules of a version
+#[post("/versions/<number>/eval", data = "<req>")]
+fn version_eval(store: State<Store>, number: u64, req: Json<EvalReq>) -> Result<String, NotFound<String>> {
+    let version = store.version(number).ok_or_else(|| NotFound(format!("version {} not found", number)))?;
+    Ok(match eval_app(&version.app, &req) {
+        Ok(app) => render(&app, &req.scope),
+        Err(e) => e.to_string(),
+    })
+}
+
+/// Makes the rules of a version current again, as a new version
+#[post("/versions/<number>/rollback")]
+fn version_rollback(store: State<Store>, number: u64) -> Result<Json<VersionInfo>, BadRequest<String>> {
+    store.rollback(number)
+        .map(|v| Json(v.info()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Syntax errors of a single rule rendered against its source
+fn render_rule_error(e: anyhow::Error, source: &str) -> String {
+    match e.downcast_ref::<SyntaxErrors>() {
+        Some(errors) => errors.render_at(source, ""),
+        None => e.to_string(),
     }
 }
 
-fn create(app: App) -> rocket::Rocket {
-    rocket::ignite().manage(app).mount("/", routes![req_get, req_post])
+/// Removes the rules of the request from the stored rule set
+#[delete("/rules", data = "<req>")]
+fn rules_delete(store: State<Store>, req: Json<Req>) -> Result<Json<RuleSet>, BadRequest<String>> {
+    store.update("remove rules", |app| req.exprs.iter().try_fold(app.clone(), |app, cur| app.remove(cur)))
+        .map(|v| Json(v.app.rule_set()))
+        .map_err(|e| BadRequest(Some(e.to_string())))
+}
+
+/// Reports the lint diagnostics and gaps of a loaded version from another thread, the rules
+/// are served meanwhile and the coverage is kept for `/coverage`
+fn warn(version: Arc<Version>) {
+    thread::spawn(move || {
+        match version.app.lint() {
+            Ok(diagnostics) => diagnostics.iter().for_each(|d| eprintln!("warning: {}", d)),
+            Err(e) => eprintln!("warning: rules not linted, {}", e),
+        }
+        let coverage = match version.coverage() {
+            Ok(c) => c,
+            Err(e) => return eprintln!("warning: coverage not checked, {}", e),
+        };
+        for gap in &coverage.gaps {
+            let region = if gap.literals.is_empty() { "any input".to_string() } else { gap.literals.join(" && ") };
+            eprintln!("warning: no rule matches {}", region);
+        }
+        for h in &coverage.missing_formulas {
+            eprintln!("warning: category {} has no formula", h);
+        }
+    });
+}
+
+fn create(rocket: rocket::Rocket, store: Store) -> rocket::Rocket {
+    rocket.manage(store).mount("/", routes![req_get, req_post, req_eval, req_explain, req_analyze, req_coverage, req_lint, req_check,
+                                            rules_get, rules_post, rules_put, rules_delete, rules_export, rules_import, table_get, table_put, dmn_get, dmn_put, logic_get, logic_post, rule_get, rule_put, rule_delete,
+                                            versions_get, version_get, version_diff, version_eval, version_rollback])
 }
 
+/// Reloads the rule file when it changes, in-flight requests finish on the rules they started with.
+/// A file which does not build is reported and the current rules are kept
+fn watch(store: Store, interval: Duration) {
+    let mut watcher = Watcher::new(store.clone());
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match watcher.poll() {
+            Ok(Some(version)) => {
+                eprintln!("{}, now version {}", version.change, version.number);
+                warn(version);
+            },
+            Ok(None) => {},
+            Err(e) => eprintln!("warning: rules not reloaded, keeping version {}\n{}", store.current().number, e),
+        }
+    });
+}
+
+/// Rules are kept in `rules.json`, another file can be set with `rules_file` in `Rocket.toml`
+/// or `ROCKET_RULES_FILE`. A missing file is created with the default rules.
+/// The file is checked for changes every `rules_reload` seconds (2 by default, 0 turns it off)
 fn main() {
-    let app = App::default();
-    create(app).launch();
+    let rocket = rocket::ignite();
+    let path = rocket.config().get_str("rules_file").unwrap_or("rules.json").to_string();
+    let reload = rocket.config().get_int("rules_reload").unwrap_or(2);
+    let store = match Store::open(&path, App::default()) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+    warn(store.current());
+    if reload > 0 {
+        watch(store.clone(), Duration::from_secs(reload as u64));
+    }
+    create(rocket, store).launch();
 }
 
 #[cfg(test)]
 mod test {
     use crate::lib::app::App;
+    use crate::lib::store::Store;
     use crate::create;
     use rocket::local::Client;
     use rocket::http::{ContentType, Status};
 
     #[test]
     fn test_rocket() {
-        let app = App::default();
-        let r = create(app);
+        let r = create(rocket::ignite(), Store::in_memory(App::default()));
         let client = Client::new(r).expect("valid rocket instance");
         let req = client.get("/true/true/true/1.0/52/1");
         let mut response = req.dispatch();
@@ -88,7 +422,242 @@ mod test {
         let req = client.post("/false/false/false/1.0/52/1");
         let req = req.body("{\"exprs\": [\"!A && !B && !\"]}");
         let mut response = req.dispatch();
-        assert_eq!(response.body_string(), Some("invalid expression".to_string()));
+        assert_eq!(response.body_string(), Some("error: unexpected end of input, expected identifier, number, `(`, `-`, `+` or `!`\n --> exprs[0]:1:14\n  |\n1 | !A && !B && !\n  |              ^".to_string()));
+
+        // named variables
+        let req = client.post("/eval");
+        let req = req.body("{\"scope\": {\"is_vip\": true, \"order_total\": 120.5, \"A\": false, \"B\": false, \"C\": false}, \
+                            \"categories\": [\"GOLD\"], \"exprs\": [\"is_vip => H = GOLD\", \"H = GOLD => K = order_total - 20\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 100.5".to_string()));
+
+        let req = client.post("/eval");
+        let req = req.body("{\"scope\": {\"is_vip\": 1, \"A\": false, \"B\": false, \"C\": false}, \"exprs\": [\"is_vip => H = M\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("variable `is_vip` is int, expected bool".to_string()));
+
+        // custom categories
+        let req = client.post("/eval");
+        let req = req.body("{\"scope\": {\"is_vip\": true, \"A\": false, \"B\": false, \"C\": false}, \
+                            \"categories\": [\"GOLD\"], \"exprs\": [\"is_vip => H = GOLD\", \"H = GOLD => K = 7\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 7".to_string()));
+
+        let req = client.post("/eval");
+        let req = req.body("{\"scope\": {\"is_vip\": true}, \"exprs\": [\"is_vip => H = GOLD\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("category GOLD is not declared".to_string()));
+
+        // conflict resolution
+        let req = client.post("/eval");
+        let req = req.body("{\"scope\": {\"A\": true, \"B\": true, \"C\": true, \"D\": 1.0, \"E\": 52, \"F\": 1}, \
+                            \"strategy\": \"collect-all\", \"exprs\": [\"A => H = T\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("Ok: P = 3, T = 0.9666666666666667".to_string()));
+
+        let req = client.post("/eval");
+        let req = req.body("{\"scope\": {\"A\": true, \"B\": true, \"C\": true, \"D\": 1.0, \"E\": 52, \"F\": 1}, \
+                            \"strategy\": \"error-on-ambiguity\", \"exprs\": [\"A => H = T\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("ambiguous match, rules produce P, T".to_string()));
+
+        // trace
+        let req = client.post("/explain");
+        let req = req.body("{\"scope\": {\"is_vip\": false}, \"exprs\": [\"is_vip => H = M\", \"H = M => K = -2\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("{\"strategy\":\"first-match\",\"rules\":[\
+                                                {\"rule\":{\"index\":0,\"condition\":\"A && B && !C\",\"category\":\"M\"},\"priority\":0,\"matched\":false,\"error\":\"unknown variable `A`\"}],\
+                                                \"selected\":[],\"error\":\"unknown variable `A`\"}".to_string()));
+
+        let req = client.post("/explain");
+        let req = req.body("{\"scope\": {\"A\": true, \"B\": true, \"C\": false, \"D\": 1.0, \"E\": 10, \"F\": 1}}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("{\"strategy\":\"first-match\",\"rules\":[\
+                                                {\"rule\":{\"index\":0,\"condition\":\"A && B && !C\",\"category\":\"M\"},\"priority\":0,\"matched\":true},\
+                                                {\"rule\":{\"index\":1,\"condition\":\"A && B && C\",\"category\":\"P\"},\"priority\":0,\"matched\":false},\
+                                                {\"rule\":{\"index\":2,\"condition\":\"!A && B && C\",\"category\":\"T\"},\"priority\":0,\"matched\":false}],\
+                                                \"selected\":[{\"rule\":0,\"category\":\"M\",\"reason\":\"first matching rule\",\
+                                                \"formula\":{\"index\":3,\"formula\":\"D + D * E / 10\",\"steps\":[\
+                                                {\"op\":\"load D\",\"stack\":[1.0]},{\"op\":\"load D\",\"stack\":[1.0,1.0]},\
+                                                {\"op\":\"load E\",\"stack\":[1.0,1.0,10.0]},{\"op\":\"mul\",\"stack\":[1.0,10.0]},\
+                                                {\"op\":\"push 10\",\"stack\":[1.0,10.0,10.0]},{\"op\":\"div\",\"stack\":[1.0,1.0]},\
+                                                {\"op\":\"add\",\"stack\":[2.0]}],\"value\":2.0}}],\"error\":null}".to_string()));
+
+        // static analysis
+        let req = client.post("/analyze").body("{}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("[]".to_string()));
+
+        let req = client.post("/analyze");
+        let req = req.body("{\"exprs\": [\"A && !C => H = P\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("[{\"first\":{\"index\":0,\"condition\":\"A && B && !C\",\"category\":\"M\"},\
+                                                \"second\":{\"index\":6,\"condition\":\"A && !C\",\"category\":\"P\"},\
+                                                \"witness\":{\"inputs\":{\"A\":true,\"B\":true,\"C\":false},\"assumptions\":[]}}]".to_string()));
+
+        let req = client.post("/analyze");
+        let req = req.body("{\"exprs\": [\"A => H = GOLD\"]}");
+        let response = req.dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let req = client.post("/coverage");
+        let req = req.body("{\"categories\": [\"X\"], \"exprs\": [\"!B => H = X\", \"!A && !C => H = M\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("{\"gaps\":[],\"missing_formulas\":[\"X\"]}".to_string()));
+
+        let req = client.post("/lint");
+        let req = req.body("{\"exprs\": [\"A && !A => H = T\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("[{\"kind\":\"unsatisfiable\",\
+                                                \"rule\":{\"index\":6,\"condition\":\"A && !A\",\"category\":\"T\"}}]".to_string()));
+
+        // every error of a batch
+        let req = client.post("/check");
+        let req = req.body("{\"exprs\": [\"A && => H = M\", \"B => H = P\", \"H = P => K = D ~ 2\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("[{\"rule\":0,\"message\":\"unexpected `=>`, expected identifier, number, `(`, `-`, `+` or `!`\",\
+                                                \"syntax\":{\"kind\":\"unexpected-token\",\"span\":{\"start\":5,\"end\":7},\
+                                                \"expected\":[\"identifier\",\"number\",\"`(`\",\"`-`\",\"`+`\",\"`!`\"],\"found\":\"`=>`\"}},\
+                                                {\"rule\":2,\"message\":\"invalid character `~`\",\
+                                                \"syntax\":{\"kind\":\"invalid-character\",\"span\":{\"start\":15,\"end\":16},\
+                                                \"expected\":[],\"found\":\"`~`\"}}]".to_string()));
+
+        let req = client.post("/check").body("{\"exprs\": [\"B => H = P\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("[]".to_string()));
+
+        // stored rules
+        let req = client.post("/rules").body("{\"categories\": [\"X\"], \"exprs\": [\"!A && !B && !C => H = X\", \"H = X => K = E\"]}");
+        let mut response = req.dispatch();
+        assert!(response.body_string().unwrap().ends_with("{\"id\":7,\"source\":\"!A && !B && !C => H = X\",\"priority\":0},\
+                                                            {\"id\":8,\"source\":\"H = X => K = E\",\"priority\":0}],\"next_id\":9}"));
+        let mut response = client.get("/false/false/false/1.0/52/1").dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 52".to_string()));
+
+        let req = client.post("/rules").body("{\"exprs\": [\"A => H = GOLD\"]}");
+        assert_eq!(req.dispatch().status(), Status::BadRequest);
+
+        let req = client.delete("/rules").body("{\"exprs\": [\"!A && !B && !C => H = X\"]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("category X is not produced by any rule".to_string()));
+        let req = client.delete("/rules").body("{\"exprs\": [\"!A && !B && !C => H = X\", \"H = X => K = E\"]}");
+        assert_eq!(req.dispatch().status(), Status::Ok);
+        let mut response = client.get("/false/false/false/1.0/52/1").dispatch();
+        assert_eq!(response.body_string(), Some("expr not found".to_string()));
+
+        let req = client.put("/rules").body("{\"categories\": [\"M\"], \"rules\": [{\"source\": \"A => H = M\"}, {\"source\": \"H = M => K = F\"}]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("{\"categories\":[\"M\"],\"strategy\":\"first-match\",\
+                                                \"rules\":[{\"id\":9,\"source\":\"A => H = M\",\"priority\":0},\
+                                                {\"id\":10,\"source\":\"H = M => K = F\",\"priority\":0}],\"next_id\":11}".to_string()));
+        let mut response = client.get("/true/false/false/1.0/52/1").dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 1".to_string()));
+
+        let req = client.put("/rules").body("{\"categories\": [], \"rules\": [{\"source\": \"A => H = M\"}]}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("error: category M is not declared\n --> rules[0]".to_string()));
+        let mut response = client.get("/rules").dispatch();
+        assert!(response.body_string().unwrap().starts_with("{\"categories\":[\"M\"]"));
+
+        // single rules by id
+        let mut response = client.get("/rules/9").dispatch();
+        assert_eq!(response.body_string(), Some("{\"id\":9,\"source\":\"A => H = M\",\"priority\":0}".to_string()));
+        assert_eq!(client.get("/rules/42").dispatch().status(), Status::NotFound);
+
+        let req = client.put("/rules/9").body("{\"name\": \"a only\", \"source\": \"A && !B => H = M\"}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("{\"id\":9,\"name\":\"a only\",\"source\":\"A && !B => H = M\",\"priority\":0}".to_string()));
+        let mut response = client.get("/true/true/false/1.0/52/1").dispatch();
+        assert_eq!(response.body_string(), Some("expr not found".to_string()));
+
+        let req = client.put("/rules/12").body("{\"source\": \"A && => H = M\"}");
+        let mut response = req.dispatch();
+        assert_eq!(response.body_string(), Some("error: unexpected `=>`, expected identifier, number, `(`, `-`, `+` or `!`\
+                                                \n --> 1:6\n  |\n1 | A && => H = M\n  |      ^^".to_string()));
+
+        let mut response = client.delete("/rules/9").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.body_string(), Some("category M is not produced by any rule".to_string()));
+        let mut response = client.delete("/rules/10").dispatch();
+        assert_eq!(response.body_string(), Some("{\"id\":10,\"source\":\"H = M => K = F\",\"priority\":0}".to_string()));
+        assert_eq!(client.delete("/rules/10").dispatch().status(), Status::NotFound);
+
+        // versions
+        let mut response = client.get("/versions").dispatch();
+        let body = response.body_string().unwrap();
+        assert_eq!(6, body.matches("\"number\"").count());
+        assert!(body.contains("{\"number\":5,\"change\":\"put rule 9\","));
+
+        let mut response = client.get("/versions/5/diff/6").dispatch();
+        assert_eq!(response.body_string(), Some("{\"categories_added\":[],\"categories_removed\":[],\"added\":[],\
+                                                \"removed\":[{\"id\":10,\"source\":\"H = M => K = F\",\"priority\":0}],\
+                                                \"changed\":[]}".to_string()));
+        assert_eq!(client.get("/versions/5/diff/42").dispatch().status(), Status::NotFound);
+
+        let eval = "{\"scope\": {\"A\": true, \"B\": false, \"F\": 7}}";
+        let mut response = client.post("/versions/4/eval").body(eval).dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 7".to_string()));
+        let mut response = client.post("/eval").body(eval).dispatch();
+        assert_eq!(response.body_string(), Some("expr for M not found".to_string()));
+
+        let mut response = client.post("/versions/4/rollback").dispatch();
+        assert!(response.body_string().unwrap().starts_with("{\"number\":7,\"change\":\"rollback to 4\","));
+        let mut response = client.post("/eval").body(eval).dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 7".to_string()));
+        assert_eq!(client.post("/versions/42/rollback").dispatch().status(), Status::BadRequest);
+
+        // documents
+        let mut response = client.get("/rules/export/yaml").dispatch();
+        assert!(response.body_string().unwrap().starts_with("---\ncategories:\n"));
+        let mut response = client.get("/rules/export/json?trees=true").dispatch();
+        assert!(response.body_string().unwrap().contains("\"condition\": {\n"));
+        assert_eq!(client.get("/rules/export/xml").dispatch().status(), Status::BadRequest);
+
+        let doc = "categories: [M]\nconditions:\n  - {category: M, condition: A}\nformulas:\n  - {category: M, formula: {mul: [{var: D}, {const: 2}]}}";
+        let response = client.put("/rules/import/yaml").body(doc).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mut response = client.get("/true/false/false/2.5/0/0").dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 5".to_string()));
+        let response = client.put("/rules/import/yaml").body("categories: [M]\nconditions:\n  - {category: P, condition: A}").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // decision tables
+        let mut response = client.get("/table").dispatch();
+        assert_eq!(response.body_string(), Some("hit policy,FIRST\nA,H\ntrue,M\n".to_string()));
+        let response = client.put("/table").body("A,B,H\ntrue,-,M\nfalse,true,M").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mut response = client.get("/false/true/false/2.5/0/0").dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 5".to_string()));
+        let mut response = client.put("/table").body("A,B,H\ntrue,yes,M").dispatch();
+        assert_eq!(response.body_string(), Some("line 2, column B: expected true, false or -, found `yes`".to_string()));
+
+        // DMN
+        let mut response = client.get("/dmn").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::XML));
+        assert!(response.body_string().unwrap().contains("<text>D * 2</text>"));
+        let dmn = "<definitions xmlns=\"https://www.omg.org/spec/DMN/20191111/MODEL/\">\
+                   <decision name=\"H\"><decisionTable hitPolicy=\"UNIQUE\">\
+                   <input><inputExpression><text>D</text></inputExpression></input><output name=\"H\"/>\
+                   <rule><inputEntry><text>&gt; 10</text></inputEntry><outputEntry><text>\"M\"</text></outputEntry></rule>\
+                   </decisionTable></decision>\
+                   <decision name=\"M\"><literalExpression><text>D - 1</text></literalExpression></decision></definitions>";
+        let response = client.put("/dmn").body(dmn).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mut response = client.get("/false/false/false/20/0/0").dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 19".to_string()));
+        let mut response = client.put("/dmn").body(dmn.replace("UNIQUE", "ANY")).dispatch();
+        assert_eq!(response.body_string(), Some("decision H: hit policy ANY is not supported, expected UNIQUE, FIRST, PRIORITY or COLLECT".to_string()));
+
+        // JSON Logic
+        let mut response = client.get("/logic").dispatch();
+        assert!(response.body_string().unwrap().contains("\"condition\":{\">\":[{\"var\":\"D\"},10]}"));
+        let rules = "[{\"category\": \"M\", \"condition\": {\"and\": [{\"var\": \"A\"}, {\"<\": [{\"var\": \"D\"}, 5]}]}}]";
+        let response = client.post("/logic").body(rules).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mut response = client.get("/true/false/false/2/0/0").dispatch();
+        assert_eq!(response.body_string(), Some("Ok: 1".to_string()));
+        let mut response = client.post("/logic").body(rules.replace("<", "in")).dispatch();
+        assert_eq!(response.body_string(), Some("error: condition.and[1]: unsupported operator `in`\n --> rules[0]".to_string()));
     }
 }
 